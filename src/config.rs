@@ -1,10 +1,13 @@
 use anyhow::Context;
+use ipnet::IpNet;
 use log::info;
 use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
@@ -13,16 +16,317 @@ pub type AppConfig = Arc<Config>;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub consul: Consul,
+    #[serde(default)]
+    pub discovery: Discovery,
+    /// Time to wait for the first discovery of the edge nodes on startup
+    #[serde(default = "default_discovery_timeout", with = "humantime_serde")]
+    pub discovery_timeout: Duration,
+    pub consul: Option<Consul>,
+    pub health_check: Option<HealthCheck>,
+    pub tracing: Option<Tracing>,
+    #[serde(default)]
+    pub node_groups: NodeGroups,
     pub playlist: Playlist,
     pub http: Http,
 }
 
+fn default_discovery_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Source of the edge nodes
+#[derive(Debug, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum Discovery {
+    /// Defaults to the service configured in the `consul` section
+    Consul {
+        #[serde(default)]
+        consul: Option<Consul>,
+    },
+    File(EdgeNodeFile),
+    DnsSrv(DnsSrv),
+    Kubernetes(Kubernetes),
+    Composite {
+        sources: Vec<DiscoverySource>,
+    },
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery::Consul { consul: None }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiscoverySource {
+    /// Sources with a lower priority are only used if those with a higher one have no edge
+    /// nodes for a node group, 0 is the highest priority
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(flatten)]
+    pub discovery: Discovery,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EdgeNodeFile {
+    pub path: PathBuf,
+    #[serde(default = "default_reload_interval", with = "humantime_serde")]
+    pub reload_interval: Duration,
+}
+
+fn default_reload_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DnsSrv {
+    /// Name of the SRV records, `{node_group}` is replaced with the node group
+    pub name: String,
+    pub node_groups: Vec<String>,
+    /// Defaults to the nameservers of `/etc/resolv.conf`, which are tried in turn
+    pub nameserver: Option<SocketAddr>,
+    #[serde(default = "default_srv_scheme")]
+    pub scheme: String,
+    #[serde(default = "default_dns_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    #[serde(default = "default_min_refresh_interval", with = "humantime_serde")]
+    pub min_refresh_interval: Duration,
+    #[serde(default = "default_max_refresh_interval", with = "humantime_serde")]
+    pub max_refresh_interval: Duration,
+}
+
+fn default_srv_scheme() -> String {
+    String::from("https")
+}
+
+fn default_dns_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_min_refresh_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_refresh_interval() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Kubernetes {
+    #[serde(default = "default_kubernetes_api_url")]
+    pub api_url: Url,
+    /// Defaults to the namespace of the service account
+    pub namespace: Option<String>,
+    pub service: String,
+    /// Name of the port of the endpoint slices, defaults to the first port
+    pub port_name: Option<String>,
+    #[serde(default = "default_service_account_path")]
+    pub service_account_path: PathBuf,
+    /// Url of endpoints without the url key, `{address}`, `{hostname}` and `{port}` are
+    /// replaced with the values of the endpoint
+    #[serde(default = "default_kubernetes_url_template")]
+    pub url_template: String,
+    #[serde(default)]
+    pub keys: KubernetesKeys,
+    #[serde(
+        default = "default_kubernetes_retry_interval",
+        with = "humantime_serde"
+    )]
+    pub retry_interval: Duration,
+    /// Time after which the api server ends a watch, it is continued with a new one
+    #[serde(default = "default_kubernetes_watch_timeout", with = "humantime_serde")]
+    pub watch_timeout: Duration,
+}
+
+fn default_kubernetes_api_url() -> Url {
+    Url::parse("https://kubernetes.default.svc").unwrap()
+}
+
+fn default_service_account_path() -> PathBuf {
+    PathBuf::from("/var/run/secrets/kubernetes.io/serviceaccount")
+}
+
+fn default_kubernetes_url_template() -> String {
+    String::from("https://{address}:{port}")
+}
+
+fn default_kubernetes_retry_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_kubernetes_watch_timeout() -> Duration {
+    Duration::from_secs(290)
+}
+
+/// Label or annotation keys of the edge node properties of the endpoint slices
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KubernetesKeys {
+    pub url: String,
+    pub node_group: String,
+    pub weight: String,
+}
+
+impl Default for KubernetesKeys {
+    fn default() -> Self {
+        KubernetesKeys {
+            url: String::from("grosp/edge-url"),
+            node_group: String::from("grosp/node-group"),
+            weight: String::from("grosp/weight"),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Consul {
     pub base_url: Url,
     #[serde(with = "humantime_serde")]
     pub update_interval: Duration,
+    #[serde(default = "default_blocking_queries")]
+    pub blocking_queries: bool,
+    #[serde(default = "default_wait_time", with = "humantime_serde")]
+    pub wait_time: Duration,
+    #[serde(default = "default_consul_service")]
+    pub service: String,
+    #[serde(default = "default_consul_tag")]
+    pub tag: Option<String>,
+    /// Filter expression of the query, see https://www.consul.io/api-docs/features/filtering
+    pub filter: Option<String>,
+    pub datacenter: Option<String>,
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub meta_keys: ConsulMetaKeys,
+    /// Time after which the edge nodes are discarded if consul can't be queried
+    #[serde(default, with = "humantime_serde")]
+    pub max_staleness: Option<Duration>,
+    /// File the last edge nodes from consul are saved to and loaded from at startup
+    pub snapshot_path: Option<PathBuf>,
+}
+
+fn default_blocking_queries() -> bool {
+    true
+}
+
+fn default_wait_time() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_consul_service() -> String {
+    String::from("edge")
+}
+
+fn default_consul_tag() -> Option<String> {
+    Some(String::from("state=active"))
+}
+
+/// Keys of the edge node properties in the consul service meta
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConsulMetaKeys {
+    pub url: String,
+    pub node_group: String,
+    pub weight: String,
+    pub load: String,
+}
+
+impl Default for ConsulMetaKeys {
+    fn default() -> Self {
+        ConsulMetaKeys {
+            url: String::from("edge_url"),
+            node_group: String::from("node_group"),
+            weight: String::from("weight"),
+            load: String::from("load"),
+        }
+    }
+}
+
+/// Active health checks of the discovered edge nodes
+#[derive(Debug, Deserialize)]
+pub struct HealthCheck {
+    pub path: String,
+    #[serde(default = "default_health_check_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(default = "default_health_check_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Consecutive failed probes after which an edge node is removed
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+}
+
+fn default_health_check_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_health_check_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tracing {
+    /// OTLP/HTTP traces endpoint of the collector, e.g. `http://collector:4318/v1/traces`
+    pub endpoint: Url,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    #[serde(default = "default_export_interval", with = "humantime_serde")]
+    pub export_interval: Duration,
+    /// Spans ending while the queue is full are dropped
+    #[serde(default = "default_max_queued_spans")]
+    pub max_queued_spans: usize,
+    /// Share of the new traces that are sampled, continued traces keep the decision of the caller
+    #[serde(default = "default_sampling_ratio")]
+    pub sampling_ratio: f64,
+    #[serde(default = "default_export_timeout", with = "humantime_serde")]
+    pub export_timeout: Duration,
+}
+
+fn default_service_name() -> String {
+    String::from("balancing-playlist-spreader")
+}
+
+fn default_export_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_queued_spans() -> usize {
+    4096
+}
+
+fn default_sampling_ratio() -> f64 {
+    1.0
+}
+
+fn default_export_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeGroups {
+    #[serde(default)]
+    pub fallbacks: HashMap<String, Vec<String>>,
+    #[serde(default = "default_fallbacks")]
+    pub default_fallbacks: Vec<String>,
+    pub origin_fallback: Option<Url>,
+    #[serde(default)]
+    pub fail_when_unavailable: bool,
+}
+
+impl Default for NodeGroups {
+    fn default() -> Self {
+        NodeGroups {
+            fallbacks: HashMap::new(),
+            default_fallbacks: default_fallbacks(),
+            origin_fallback: None,
+            fail_when_unavailable: false,
+        }
+    }
+}
+
+fn default_fallbacks() -> Vec<String> {
+    vec![String::from("std")]
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,6 +334,99 @@ pub struct Playlist {
     pub upstream_base_url: Url,
     pub segment_signing: SegmentSigning,
     pub jwt_validation: JwtValidation,
+    #[serde(default)]
+    pub key_delivery: Vec<KeyDelivery>,
+    pub dash: Option<Dash>,
+    #[serde(default)]
+    pub distribution: Distribution,
+    #[serde(default)]
+    pub upstream: Upstream,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Upstream {
+    #[serde(default = "default_connect_timeout", with = "humantime_serde")]
+    pub connect_timeout: Duration,
+    /// Longest pause while receiving the response
+    #[serde(default = "default_read_timeout", with = "humantime_serde")]
+    pub read_timeout: Duration,
+    #[serde(default = "default_upstream_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Replaces the read and total timeout of Low-Latency HLS blocking playlist reloads,
+    /// which are held by the upstream until the requested segment is available
+    #[serde(default = "default_blocking_timeout", with = "humantime_serde")]
+    pub blocking_timeout: Duration,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_retry_backoff", with = "humantime_serde")]
+    pub retry_backoff: Duration,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreaker,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Upstream {
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            timeout: default_upstream_timeout(),
+            blocking_timeout: default_blocking_timeout(),
+            max_retries: default_max_retries(),
+            retry_backoff: default_retry_backoff(),
+            circuit_breaker: CircuitBreaker::default(),
+        }
+    }
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_read_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_upstream_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_blocking_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+fn default_retry_backoff() -> Duration {
+    Duration::from_millis(100)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CircuitBreaker {
+    /// Consecutive failed requests after which the requests to the upstream fail fast
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Time until a single request is let through to check whether the upstream recovered
+    #[serde(default = "default_open_duration", with = "humantime_serde")]
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            failure_threshold: default_failure_threshold(),
+            open_duration: default_open_duration(),
+        }
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_duration() -> Duration {
+    Duration::from_secs(10)
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +436,44 @@ pub struct SegmentSigning {
     pub duration: Duration,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Dash {
+    #[serde(with = "humantime_serde")]
+    pub signing_duration: Duration,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum Distribution {
+    #[default]
+    Random,
+    SessionAffinity {
+        #[serde(default = "default_virtual_nodes")]
+        virtual_nodes: usize,
+    },
+    SegmentAffinity,
+    LoadAware {
+        #[serde(default = "default_max_load")]
+        max_load: f64,
+    },
+}
+
+fn default_virtual_nodes() -> usize {
+    100
+}
+
+fn default_max_load() -> f64 {
+    0.9
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyDelivery {
+    #[serde(with = "serde_regex")]
+    pub uri_pattern: Regex,
+    pub base_url: Option<Url>,
+    pub signing: Option<SegmentSigning>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct JwtValidation {
     pub secret: String,
@@ -49,6 +484,24 @@ pub struct JwtValidation {
 #[derive(Debug, Deserialize)]
 pub struct Http {
     pub socket: SocketAddr,
+    /// Writes a JSON line per playlist and manifest request to stdout
+    #[serde(default = "default_access_log")]
+    pub access_log: bool,
+    /// Path of the playlist route as requested by the viewers,
+    /// differs from `/playlist/` behind a proxy that adds a prefix
+    #[serde(default = "default_playlist_path")]
+    pub playlist_path: String,
+    /// Proxies whose `X-Forwarded-For` entries are taken into account
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+fn default_access_log() -> bool {
+    true
+}
+
+fn default_playlist_path() -> String {
+    String::from("/playlist/")
 }
 
 pub fn load_config() -> anyhow::Result<AppConfig> {
@@ -1,18 +1,81 @@
+mod composite;
 pub mod consul;
+mod dns_srv;
+mod fallback;
+mod file;
+mod health_check;
+mod kubernetes;
+mod readiness;
+mod snapshot;
 
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use url::Url;
 
+pub use composite::CompositeEdgeNodeProvider;
 pub use consul::ConsulEdgeNodeProvider;
+pub use dns_srv::DnsSrvEdgeNodeProvider;
+pub use fallback::NodeGroupFallback;
+pub use file::FileEdgeNodeProvider;
+pub use health_check::HealthCheckedEdgeNodeProvider;
+pub use kubernetes::KubernetesEdgeNodeProvider;
+pub use readiness::Readiness;
 
 pub trait EdgeNodeProvider: Send + Sync {
     fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList;
+
+    /// Becomes ready once the edge nodes were discovered for the first time
+    fn readiness(&self) -> Readiness {
+        Readiness::ready()
+    }
+}
+
+impl<T: EdgeNodeProvider + ?Sized> EdgeNodeProvider for Arc<T> {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        (**self).get_edge_nodes(node_group)
+    }
+
+    fn readiness(&self) -> Readiness {
+        (**self).readiness()
+    }
 }
 
 pub type EdgeNodeList = Arc<Vec<EdgeNode>>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EdgeNode {
     pub url: Url,
     pub group: String,
+    /// Share of the traffic relative to the other edge nodes of the group
+    pub weight: u32,
+    /// Utilisation between 0 and 1 as reported by the edge node
+    pub load: Option<f64>,
+}
+
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// Edge node lists of the discovered edge nodes by node group
+type EdgeNodeStorage = RwLock<HashMap<String, EdgeNodeList>>;
+
+fn group_edge_nodes(edge_nodes: Vec<EdgeNode>) -> HashMap<String, EdgeNodeList> {
+    let grouped: HashMap<String, Vec<EdgeNode>> =
+        edge_nodes.into_iter().fold(HashMap::new(), |mut acc, en| {
+            acc.entry(en.group.clone())
+                .or_insert_with(Vec::new)
+                .push(en);
+
+            acc
+        });
+
+    grouped.into_iter().map(|(k, v)| (k, Arc::new(v))).collect()
+}
+
+fn stored_edge_nodes(storage: &EdgeNodeStorage, node_group: &str) -> EdgeNodeList {
+    let grouped = storage.read().unwrap();
+
+    grouped
+        .get(node_group)
+        .map(Arc::clone)
+        .unwrap_or_else(|| Arc::new(vec![]))
 }
@@ -0,0 +1,236 @@
+use super::{EdgeNode, EdgeNodeList, EdgeNodeProvider, Readiness};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    struct MockEdgeNodeProvider(Vec<(&'static str, &'static str)>);
+
+    impl EdgeNodeProvider for MockEdgeNodeProvider {
+        fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+            let edge_nodes = self
+                .0
+                .iter()
+                .filter(|(group, _)| *group == node_group)
+                .map(|(group, url)| EdgeNode {
+                    url: Url::parse(url).unwrap(),
+                    group: String::from(*group),
+                    weight: 1,
+                    load: None,
+                })
+                .collect();
+
+            Arc::new(edge_nodes)
+        }
+    }
+
+    /// Hands out the same list on every call, like the real providers.
+    struct FixedEdgeNodeProvider(EdgeNodeList);
+
+    impl EdgeNodeProvider for FixedEdgeNodeProvider {
+        fn get_edge_nodes(&self, _node_group: &str) -> EdgeNodeList {
+            Arc::clone(&self.0)
+        }
+    }
+
+    fn source(
+        priority: u32,
+        edge_nodes: Vec<(&'static str, &'static str)>,
+    ) -> (u32, Arc<dyn EdgeNodeProvider>) {
+        (priority, Arc::new(MockEdgeNodeProvider(edge_nodes)))
+    }
+
+    fn hosts(edge_nodes: EdgeNodeList) -> Vec<String> {
+        edge_nodes
+            .iter()
+            .map(|en| en.url.host_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_sources_of_same_priority() {
+        let provider = CompositeEdgeNodeProvider::new(vec![
+            source(
+                0,
+                vec![("std", "https://alpha.com"), ("std", "https://beta.com")],
+            ),
+            source(
+                0,
+                vec![("std", "https://beta.com"), ("std", "https://gamma.com")],
+            ),
+        ]);
+
+        assert_eq!(
+            vec!["alpha.com", "beta.com", "gamma.com"],
+            hosts(provider.get_edge_nodes("std"))
+        );
+    }
+
+    #[test]
+    fn test_merged_edge_nodes_are_cached() {
+        let edge_nodes = |url: &str| -> EdgeNodeList {
+            Arc::new(vec![EdgeNode {
+                url: Url::parse(url).unwrap(),
+                group: String::from("std"),
+                weight: 1,
+                load: None,
+            }])
+        };
+        let changing = Arc::new(Mutex::new(edge_nodes("https://beta.com")));
+
+        struct ChangingEdgeNodeProvider(Arc<Mutex<EdgeNodeList>>);
+
+        impl EdgeNodeProvider for ChangingEdgeNodeProvider {
+            fn get_edge_nodes(&self, _node_group: &str) -> EdgeNodeList {
+                Arc::clone(&self.0.lock().unwrap())
+            }
+        }
+
+        let provider = CompositeEdgeNodeProvider::new(vec![
+            (
+                0,
+                Arc::new(FixedEdgeNodeProvider(edge_nodes("https://alpha.com"))),
+            ),
+            (0, Arc::new(ChangingEdgeNodeProvider(Arc::clone(&changing)))),
+        ]);
+
+        let merged = provider.get_edge_nodes("std");
+        assert!(Arc::ptr_eq(&merged, &provider.get_edge_nodes("std")));
+
+        *changing.lock().unwrap() = edge_nodes("https://gamma.com");
+
+        let remerged = provider.get_edge_nodes("std");
+        assert!(!Arc::ptr_eq(&merged, &remerged));
+        assert_eq!(vec!["alpha.com", "gamma.com"], hosts(remerged));
+    }
+
+    #[test]
+    fn test_lower_priority_only_without_edge_nodes() {
+        let provider = CompositeEdgeNodeProvider::new(vec![
+            source(
+                1,
+                vec![
+                    ("std", "https://emergency.com"),
+                    ("premium", "https://emergency.com"),
+                ],
+            ),
+            source(0, vec![("std", "https://alpha.com")]),
+        ]);
+
+        assert_eq!(vec!["alpha.com"], hosts(provider.get_edge_nodes("std")));
+        assert_eq!(
+            vec!["emergency.com"],
+            hosts(provider.get_edge_nodes("premium"))
+        );
+        assert!(provider.get_edge_nodes("unknown").is_empty());
+    }
+}
+
+/// Merges the edge nodes of several providers, edge nodes with the same url are only used once.
+///
+/// The providers with the highest priority, i.e. the lowest value, that have edge nodes for a
+/// node group are used, the others are ignored for that group.
+pub struct CompositeEdgeNodeProvider {
+    sources: Vec<(u32, Arc<dyn EdgeNodeProvider>)>,
+    readiness: Readiness,
+    /// Merged edge nodes per node group together with the lists they were merged from
+    merged: Mutex<HashMap<String, (Vec<EdgeNodeList>, EdgeNodeList)>>,
+}
+
+impl CompositeEdgeNodeProvider {
+    pub fn new(mut sources: Vec<(u32, Arc<dyn EdgeNodeProvider>)>) -> CompositeEdgeNodeProvider {
+        sources.sort_by_key(|(priority, _)| *priority);
+
+        // the sources are fallbacks of each other, one of them is enough to serve edge nodes
+        let readiness = Readiness::any(
+            sources
+                .iter()
+                .map(|(_, provider)| provider.readiness())
+                .collect(),
+        );
+
+        CompositeEdgeNodeProvider {
+            sources,
+            readiness,
+            merged: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn merge(
+        &self,
+        node_group: &str,
+        mut edge_node_lists: Vec<EdgeNodeList>,
+    ) -> Option<EdgeNodeList> {
+        if edge_node_lists.len() <= 1 {
+            return edge_node_lists.pop();
+        }
+
+        let mut merged = self.merged.lock().unwrap();
+
+        // the providers hand out the same lists until their edge nodes change
+        if let Some((merged_lists, edge_nodes)) = merged.get(node_group) {
+            let unchanged = merged_lists.len() == edge_node_lists.len()
+                && merged_lists
+                    .iter()
+                    .zip(&edge_node_lists)
+                    .all(|(merged_list, edge_nodes)| Arc::ptr_eq(merged_list, edge_nodes));
+
+            if unchanged {
+                return Some(Arc::clone(edge_nodes));
+            }
+        }
+
+        let edge_nodes = Arc::new(merge(&edge_node_lists));
+        merged.insert(
+            node_group.to_string(),
+            (edge_node_lists, Arc::clone(&edge_nodes)),
+        );
+
+        Some(edge_nodes)
+    }
+}
+
+impl EdgeNodeProvider for CompositeEdgeNodeProvider {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        let mut remaining = &self.sources[..];
+
+        while let Some((priority, _)) = remaining.first() {
+            let same_priority = remaining
+                .iter()
+                .take_while(|(other, _)| other == priority)
+                .count();
+
+            let edge_node_lists: Vec<EdgeNodeList> = remaining[..same_priority]
+                .iter()
+                .map(|(_, provider)| provider.get_edge_nodes(node_group))
+                .filter(|edge_nodes| !edge_nodes.is_empty())
+                .collect();
+
+            if let Some(edge_nodes) = self.merge(node_group, edge_node_lists) {
+                return edge_nodes;
+            }
+
+            remaining = &remaining[same_priority..];
+        }
+
+        Arc::new(vec![])
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+}
+
+fn merge(edge_node_lists: &[EdgeNodeList]) -> Vec<EdgeNode> {
+    let mut urls = HashSet::new();
+
+    edge_node_lists
+        .iter()
+        .flat_map(|edge_nodes| edge_nodes.iter())
+        .filter(|edge_node| urls.insert(&edge_node.url))
+        .cloned()
+        .collect()
+}
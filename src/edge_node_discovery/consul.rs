@@ -1,157 +1,653 @@
-use consul_api_client::Client;
+use crate::config;
+use crate::metrics;
+use crate::trace::{self, SpanKind};
+use anyhow::Context;
 use log::{error, info, warn};
+use reqwest::Client;
+use serde::Deserialize;
 use std::sync::{Arc, RwLock, Weak};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time;
 use url::Url;
 
-use super::{EdgeNode, EdgeNodeList, EdgeNodeProvider};
-use consul_api_client::health::{Health, ServiceEntry};
+use super::readiness::ReadySignal;
+use super::snapshot::Snapshot;
+use super::{
+    group_edge_nodes, stored_edge_nodes, EdgeNode, EdgeNodeList, EdgeNodeProvider, EdgeNodeStorage,
+    Readiness, DEFAULT_WEIGHT,
+};
 use std::collections::HashMap;
-use std::convert::{TryFrom, TryInto};
 
-type EdgeNodeStorage = RwLock<HashMap<String, EdgeNodeList>>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn consul_config() -> config::Consul {
+        config::Consul {
+            base_url: Url::parse("http://consul:8500").unwrap(),
+            update_interval: Duration::from_secs(1),
+            blocking_queries: true,
+            wait_time: Duration::from_secs(60),
+            service: String::from("edge-staging"),
+            tag: Some(String::from("state=active")),
+            filter: Some(String::from("Service.Meta.tier == \"hot\"")),
+            datacenter: Some(String::from("fra1")),
+            namespace: Some(String::from("streaming")),
+            meta_keys: config::ConsulMetaKeys {
+                url: String::from("public_url"),
+                ..Default::default()
+            },
+            max_staleness: None,
+            snapshot_path: None,
+        }
+    }
+
+    #[test]
+    fn test_request_url() {
+        let query = ServiceQuery::from_config(&consul_config()).unwrap();
+
+        assert_eq!(
+            "http://consul:8500/v1/health/service/edge-staging?passing=true&tag=state%3Dactive\
+             &filter=Service.Meta.tier+%3D%3D+%22hot%22&dc=fra1&ns=streaming",
+            query.request_url(None).as_str()
+        );
+        assert!(query
+            .request_url(Some(42))
+            .as_str()
+            .ends_with("&index=42&wait=60000ms"));
+
+        let interval_polling = ServiceQuery::from_config(&config::Consul {
+            blocking_queries: false,
+            ..consul_config()
+        })
+        .unwrap();
+
+        assert_eq!(
+            interval_polling.request_url(None),
+            interval_polling.request_url(Some(42))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_edge_nodes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let query = ServiceQuery::from_config(&config::Consul {
+            base_url: Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap(),
+            ..consul_config()
+        })
+        .unwrap();
+
+        let mock_consul = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut request = [0; 1024];
+            let length = stream.read(&mut request).unwrap();
+            let body = r#"[
+                {
+                    "Node": {"Node": "edge-1", "Address": "10.0.0.1"},
+                    "Service": {
+                        "Service": "edge-staging",
+                        "Meta": {"public_url": "https://edge-1.com", "node_group": "std"}
+                    },
+                    "Checks": []
+                },
+                {"Node": {"Node": "edge-2"}, "Service": {"Meta": null}}
+            ]"#;
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nX-Consul-Index: 7\r\nConnection: close\r\n\r\n{}",
+                body
+            )
+            .unwrap();
+
+            String::from_utf8_lossy(&request[..length]).into_owned()
+        });
+
+        let (edge_nodes, index) = query.fetch_edge_nodes(&Client::new(), None).await.unwrap();
+        let request = mock_consul.join().unwrap();
+
+        assert!(request.starts_with(
+            "GET /v1/health/service/edge-staging?passing=true&tag=state%3Dactive\
+             &filter=Service.Meta.tier+%3D%3D+%22hot%22&dc=fra1&ns=streaming "
+        ));
+        assert_eq!(Some(7), index);
+        assert_eq!(
+            vec![EdgeNode {
+                url: Url::parse("https://edge-1.com").unwrap(),
+                group: String::from("std"),
+                weight: 1,
+                load: None,
+            }],
+            edge_nodes
+        );
+    }
+
+    #[test]
+    fn test_next_index() {
+        assert_eq!(Some(7), next_index(None, Some(7)));
+        assert_eq!(Some(9), next_index(Some(7), Some(9)));
+        assert_eq!(None, next_index(Some(9), Some(7)));
+        assert_eq!(Some(1), next_index(None, Some(0)));
+        assert_eq!(None, next_index(Some(7), None));
+    }
+
+    #[test]
+    fn test_query_delay() {
+        let second = Duration::from_secs(1);
+
+        assert_eq!(second, query_delay(second, false, second * 60));
+        assert_eq!(second, query_delay(second, true, Duration::from_secs(0)));
+        assert_eq!(second / 4, query_delay(second, true, second * 3 / 4));
+        assert_eq!(
+            Duration::from_secs(0),
+            query_delay(second, true, second * 60)
+        );
+    }
+
+    #[test]
+    fn test_error_backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+
+        let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 5, 5], delays);
+
+        backoff.reset();
+        assert_eq!(Duration::from_secs(1), backoff.next_delay());
+    }
+
+    #[test]
+    fn test_service_meta_conversion() {
+        let query = ServiceQuery::from_config(&consul_config()).unwrap();
+        let meta = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            Ok(EdgeNode {
+                url: Url::parse("https://edge-1.com").unwrap(),
+                group: String::from("std"),
+                weight: 3,
+                load: None,
+            }),
+            query.convert_service_meta(&meta(&[
+                ("public_url", "https://edge-1.com"),
+                ("node_group", "std"),
+                ("weight", "3"),
+            ]))
+        );
+
+        assert!(query
+            .convert_service_meta(&meta(&[
+                ("edge_url", "https://edge-2.com"),
+                ("node_group", "std"),
+            ]))
+            .is_err());
+        assert!(query.convert_service_meta(&meta(&[])).is_err());
+    }
+
+    #[test]
+    fn test_stale_edge_nodes() {
+        let edge_node = EdgeNode {
+            url: Url::parse("https://edge-1.com").unwrap(),
+            group: String::from("std"),
+            weight: 1,
+            load: None,
+        };
+        let provider = ConsulEdgeNodeProvider {
+            discovered: Arc::new(DiscoveredEdgeNodes::default()),
+            max_staleness: Some(Duration::from_secs(60)),
+            readiness: Readiness::ready(),
+        };
+
+        provider.discovered.update(vec![edge_node.clone()], None);
+        assert!(provider.get_edge_nodes("std").is_empty());
+
+        let confirmed_at = Instant::now();
+        provider.discovered.confirm(Some(confirmed_at));
+        assert_eq!(vec![edge_node.clone()], *provider.get_edge_nodes("std"));
+        assert_eq!(
+            vec![edge_node],
+            *provider.edge_nodes_at("std", confirmed_at + Duration::from_secs(60))
+        );
+        assert!(provider
+            .edge_nodes_at("std", confirmed_at + Duration::from_secs(61))
+            .is_empty());
+    }
+}
+
+const MAX_ERROR_BACKOFF: Duration = Duration::from_secs(60);
 
 pub struct ConsulEdgeNodeProvider {
-    edge_nodes: Arc<EdgeNodeStorage>,
+    discovered: Arc<DiscoveredEdgeNodes>,
+    max_staleness: Option<Duration>,
+    readiness: Readiness,
 }
 
 impl EdgeNodeProvider for ConsulEdgeNodeProvider {
     fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
-        self.current_edge_nodes(node_group)
+        self.edge_nodes_at(node_group, Instant::now())
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.readiness.clone()
     }
 }
 
 impl ConsulEdgeNodeProvider {
-    pub fn new(consul: Client, update_interval: Duration) -> Self {
-        let edge_nodes = Arc::new(RwLock::new(HashMap::new()));
+    fn edge_nodes_at(&self, node_group: &str, now: Instant) -> EdgeNodeList {
+        if self.discovered.is_stale(self.max_staleness, now) {
+            return Arc::new(vec![]);
+        }
+
+        stored_edge_nodes(&self.discovered.edge_nodes, node_group)
+    }
+
+    pub fn new(http_client: Client, config: &config::Consul) -> anyhow::Result<Self> {
+        let query = ServiceQuery::from_config(config)?;
+        let snapshot = config.snapshot_path.clone().map(Snapshot::new);
+        let discovered = Arc::new(DiscoveredEdgeNodes::default());
+        let (mut ready_signal, readiness) = Readiness::pending();
+
+        if let Some(snapshot) = &snapshot {
+            match snapshot.load() {
+                Ok((edge_nodes, age)) => {
+                    info!("Restored edge nodes of {:?} ago from snapshot", age);
+
+                    discovered.update(edge_nodes, Instant::now().checked_sub(age));
+
+                    if !discovered.is_stale(config.max_staleness, Instant::now()) {
+                        ready_signal.set_ready();
+                    }
+                }
+                Err(e) => warn!("Failed to restore edge nodes from snapshot: {:?}", e),
+            }
+        }
+
+        metrics::CONSUL_EDGE_NODES_AGE.set_age_fn(
+            &[
+                &config.service,
+                config.datacenter.as_deref().unwrap_or_default(),
+            ],
+            {
+                let discovered = Arc::downgrade(&discovered);
+                move || discovered.upgrade()?.age(Instant::now())
+            },
+        );
 
         let provider = ConsulEdgeNodeProvider {
-            edge_nodes: Arc::clone(&edge_nodes),
+            discovered: Arc::clone(&discovered),
+            max_staleness: config.max_staleness,
+            readiness,
         };
 
-        start_update_edge_nodes_loop(Arc::downgrade(&edge_nodes), consul, update_interval);
+        start_update_edge_nodes_loop(
+            Arc::downgrade(&discovered),
+            http_client,
+            query,
+            snapshot,
+            ready_signal,
+            config.update_interval,
+        );
+
+        Ok(provider)
+    }
+}
+
+/// Edge nodes of the last successful query.
+#[derive(Default)]
+struct DiscoveredEdgeNodes {
+    edge_nodes: EdgeNodeStorage,
+    confirmed_at: RwLock<Option<Instant>>,
+}
 
-        provider
+impl DiscoveredEdgeNodes {
+    fn update(&self, edge_nodes: Vec<EdgeNode>, confirmed_at: Option<Instant>) {
+        *self.edge_nodes.write().unwrap() = group_edge_nodes(edge_nodes);
+        self.confirm(confirmed_at);
     }
 
-    fn current_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
-        let grouped = self.edge_nodes.read().unwrap();
-        let wanted = grouped.get(node_group);
+    fn confirm(&self, confirmed_at: Option<Instant>) {
+        *self.confirmed_at.write().unwrap() = confirmed_at;
+    }
 
-        wanted
-            .or_else(|| grouped.get("std")) // todo configurable
-            .map(|list| Arc::clone(list))
-            .unwrap_or_else(|| Arc::new(vec![]))
+    fn age(&self, now: Instant) -> Option<Duration> {
+        self.confirmed_at
+            .read()
+            .unwrap()
+            .map(|confirmed_at| now.saturating_duration_since(confirmed_at))
+    }
+
+    fn is_stale(&self, max_staleness: Option<Duration>, now: Instant) -> bool {
+        match (max_staleness, self.age(now)) {
+            (Some(max_staleness), Some(age)) => age > max_staleness,
+            // without an instant the edge nodes are older than the process, e.g. restored
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
     }
 }
 
 fn start_update_edge_nodes_loop(
-    edge_nodes: Weak<EdgeNodeStorage>,
-    consul: Client,
+    discovered: Weak<DiscoveredEdgeNodes>,
+    http_client: Client,
+    query: ServiceQuery,
+    snapshot: Option<Snapshot>,
+    ready_signal: ReadySignal,
     update_interval: Duration,
 ) {
     info!("Start update edge nodes loop");
 
-    tokio::spawn(async move { update_edge_nodes_loop(edge_nodes, consul, update_interval).await });
+    tokio::spawn(async move {
+        update_edge_nodes_loop(
+            discovered,
+            http_client,
+            query,
+            snapshot,
+            ready_signal,
+            update_interval,
+        )
+        .await
+    });
 }
 
 async fn update_edge_nodes_loop(
-    edge_nodes: Weak<EdgeNodeStorage>,
-    client: Client,
+    discovered: Weak<DiscoveredEdgeNodes>,
+    http_client: Client,
+    query: ServiceQuery,
+    mut snapshot: Option<Snapshot>,
+    mut ready_signal: ReadySignal,
     update_interval: Duration,
 ) {
-    let mut interval = time::interval(update_interval);
+    let mut index = None;
+    let mut backoff = Backoff::new(update_interval, MAX_ERROR_BACKOFF);
 
     loop {
-        interval.tick().await;
+        let started_at = Instant::now();
+        let mut span = trace::tracer().start_span("consul_refresh", SpanKind::Client, None);
+        if let Some(index) = index {
+            span.set_attribute("consul.index", index);
+        }
 
-        let edge_nodes = match edge_nodes.upgrade() {
-            Some(provider) => provider,
+        let result = query.fetch_edge_nodes(&http_client, index).await;
+
+        if let Err(e) = &result {
+            span.set_error(format!("{:#}", e));
+        }
+        drop(span);
+
+        // the storage is only upgraded after the query, a blocking query may take minutes
+        let discovered = match discovered.upgrade() {
+            Some(discovered) => discovered,
             None => {
                 info!("Couldn't get reference to the edge node storage, ending update loop");
                 break;
             }
         };
 
-        match fetch_edge_nodes_from_consul(&client).await {
-            Ok(new_edge_nodes) => {
-                info!("Updating edge nodes from consul: {:?}", &new_edge_nodes);
+        match result {
+            Ok((new_edge_nodes, new_index)) => {
+                metrics::CONSUL_REFRESHES
+                    .with_label_values(&["success"])
+                    .inc();
+                backoff.reset();
+
+                let changed = new_index.is_none() || new_index != index;
+
+                if let Some(snapshot) = &mut snapshot {
+                    snapshot.store(&new_edge_nodes, changed).await;
+                }
+
+                if changed {
+                    info!("Updating edge nodes from consul: {:?}", &new_edge_nodes);
 
-                let grouped: HashMap<String, Vec<EdgeNode>> =
-                    new_edge_nodes
-                        .into_iter()
-                        .fold(HashMap::new(), |mut acc, en| {
-                            acc.entry(en.group.clone())
-                                .or_insert_with(Vec::new)
-                                .push(en);
+                    discovered.update(new_edge_nodes, Some(Instant::now()));
+                } else {
+                    discovered.confirm(Some(Instant::now()));
+                }
 
-                            acc
-                        });
+                ready_signal.set_ready();
 
-                *edge_nodes.write().unwrap() =
-                    grouped.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+                index = next_index(index, new_index);
+                let blocking = query.wait_time.is_some() && index.is_some();
+
+                drop(discovered);
+                time::delay_for(query_delay(update_interval, blocking, started_at.elapsed())).await;
             }
             Err(e) => {
-                error!("Failed to update edge nodes from consul: {}", e);
-                continue;
+                error!("Failed to update edge nodes from consul: {:?}", e);
+                metrics::CONSUL_REFRESHES
+                    .with_label_values(&["failure"])
+                    .inc();
+
+                index = None;
+                drop(discovered);
+                time::delay_for(backoff.next_delay()).await;
             }
         }
     }
 }
 
-async fn fetch_edge_nodes_from_consul(client: &Client) -> anyhow::Result<Vec<EdgeNode>> {
-    let service_name = "edge"; // todo configuration
-    let (services, _meta) = client
-        .service(service_name, Some("state=active"), true, None, None)
-        .await
-        .map_err(anyhow::Error::new)?;
+/// Index of the next blocking query,
+/// see https://www.consul.io/api-docs/features/blocking#implementation-details
+fn next_index(previous: Option<u64>, received: Option<u64>) -> Option<u64> {
+    match (previous, received) {
+        // the index went backwards, e.g. after a consul restart, start over
+        (Some(previous), Some(received)) if received < previous => None,
+        (_, Some(0)) => Some(1),
+        (_, received) => received,
+    }
+}
 
-    let edge_nodes = services
-        .into_iter()
-        .filter_map(try_to_convert_service_entry_to_edge_node)
-        .collect();
+/// Delay until the next query after a successful one.
+///
+/// Blocking queries return as soon as the service changes, they are spaced by at least
+/// `update_interval` so that flapping health checks don't flood consul with queries.
+fn query_delay(update_interval: Duration, blocking: bool, elapsed: Duration) -> Duration {
+    match blocking {
+        true => update_interval
+            .checked_sub(elapsed)
+            .unwrap_or_else(|| Duration::from_secs(0)),
+        false => update_interval,
+    }
+}
 
-    Ok(edge_nodes)
+/// Exponentially growing delay between failed consul queries.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
 }
 
-fn try_to_convert_service_entry_to_edge_node(service_entry: ServiceEntry) -> Option<EdgeNode> {
-    let se = &service_entry;
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
 
-    let en: Result<EdgeNode, _> = se.try_into();
+        delay
+    }
 
-    en.map(Some).unwrap_or_else(|e| {
-        warn!(
-            "Failed to convert consul service entry into edge node; Error: {} for node: {}",
-            e, service_entry.Node.Node
-        );
-        None
-    })
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Query of the healthy instances of the edge node service,
+/// see https://www.consul.io/api-docs/health#list-nodes-for-service
+struct ServiceQuery {
+    url: Url,
+    meta_keys: config::ConsulMetaKeys,
+    wait_time: Option<Duration>,
 }
 
-impl TryFrom<&ServiceEntry> for EdgeNode {
-    type Error = &'static str;
+impl ServiceQuery {
+    fn from_config(config: &config::Consul) -> anyhow::Result<ServiceQuery> {
+        let mut url = config.base_url.clone();
+
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid consul base url `{}`", config.base_url))?
+            .pop_if_empty()
+            .extend(&["v1", "health", "service", &config.service]);
 
-    fn try_from(value: &ServiceEntry) -> Result<Self, Self::Error> {
-        let url = value
-            .Service
-            .Meta
-            .get("edge_url")
-            .ok_or("Missing edge_url in service meta")
-            .and_then(|url| Url::parse(url.as_ref()).map_err(|_| "Failed to parse edge_url"))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("passing", "true");
 
-        let group = value
-            .Service
-            .Meta
-            .get("node_group")
-            .ok_or("Missing node_group in service meta")?;
+            let optional_params = [
+                ("tag", &config.tag),
+                ("filter", &config.filter),
+                ("dc", &config.datacenter),
+                ("ns", &config.namespace),
+            ];
 
-        Ok(Self {
+            for (name, value) in optional_params.iter() {
+                if let Some(value) = value {
+                    query.append_pair(name, value);
+                }
+            }
+        }
+
+        Ok(ServiceQuery {
+            url,
+            meta_keys: config.meta_keys.clone(),
+            wait_time: Some(config.wait_time).filter(|_| config.blocking_queries),
+        })
+    }
+
+    /// Url of the query, blocking until the result differs from `index` if one is given.
+    fn request_url(&self, index: Option<u64>) -> Url {
+        let mut url = self.url.clone();
+
+        if let (Some(index), Some(wait_time)) = (index, self.wait_time) {
+            url.query_pairs_mut()
+                .append_pair("index", &index.to_string())
+                .append_pair("wait", &format!("{}ms", wait_time.as_millis()));
+        }
+
+        url
+    }
+
+    async fn fetch_edge_nodes(
+        &self,
+        http_client: &Client,
+        index: Option<u64>,
+    ) -> anyhow::Result<(Vec<EdgeNode>, Option<u64>)> {
+        let url = self.request_url(index);
+        let mut request = http_client.get(url.clone());
+
+        if let Some(wait_time) = self.wait_time {
+            // consul adds up to 1/16 of the wait time as jitter
+            request = request.timeout(wait_time + wait_time / 16 + Duration::from_secs(10));
+        }
+
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to query consul at `{}`", url))?;
+
+        let new_index = response
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|index| index.to_str().ok())
+            .and_then(|index| index.parse().ok());
+
+        let body = response
+            .bytes()
+            .await
+            .context("Failed to retrieve body of the consul response")?;
+
+        let services: Vec<ServiceEntry> =
+            serde_json::from_slice(&body).context("Failed to parse consul service entries")?;
+
+        let edge_nodes = services
+            .iter()
+            .filter_map(|se| self.try_to_convert_service_entry_to_edge_node(se))
+            .collect();
+
+        Ok((edge_nodes, new_index))
+    }
+
+    fn try_to_convert_service_entry_to_edge_node(
+        &self,
+        service_entry: &ServiceEntry,
+    ) -> Option<EdgeNode> {
+        let no_meta = HashMap::new();
+        let meta = service_entry.service.meta.as_ref().unwrap_or(&no_meta);
+
+        self.convert_service_meta(meta)
+            .map_err(|e| {
+                warn!(
+                    "Failed to convert consul service entry into edge node; Error: {} for node: {}",
+                    e, service_entry.node.node
+                )
+            })
+            .ok()
+    }
+
+    fn convert_service_meta(&self, meta: &HashMap<String, String>) -> Result<EdgeNode, String> {
+        let url = meta
+            .get(&self.meta_keys.url)
+            .ok_or_else(|| format!("Missing {} in service meta", self.meta_keys.url))
+            .and_then(|url| {
+                Url::parse(url).map_err(|_| format!("Failed to parse {}", self.meta_keys.url))
+            })?;
+
+        let group = meta
+            .get(&self.meta_keys.node_group)
+            .ok_or_else(|| format!("Missing {} in service meta", self.meta_keys.node_group))?;
+
+        let weight = match meta.get(&self.meta_keys.weight) {
+            Some(weight) => weight.parse().map_err(|_| {
+                format!("Failed to parse {} in service meta", self.meta_keys.weight)
+            })?,
+            None => DEFAULT_WEIGHT,
+        };
+
+        // the load changes frequently, a node with an unreadable load is still usable
+        let load = meta
+            .get(&self.meta_keys.load)
+            .and_then(|load| load.parse().ok());
+
+        Ok(EdgeNode {
             url,
             group: group.clone(),
+            weight,
+            load,
         })
     }
 }
 
-#[cfg(test)]
-mod tests {}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceEntry {
+    node: ServiceNode,
+    service: AgentService,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceNode {
+    node: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AgentService {
+    meta: Option<HashMap<String, String>>,
+}
@@ -0,0 +1,577 @@
+mod message;
+
+use super::readiness::ReadySignal;
+use super::{
+    stored_edge_nodes, EdgeNode, EdgeNodeList, EdgeNodeProvider, EdgeNodeStorage, Readiness,
+};
+use crate::config;
+use anyhow::{anyhow, bail, Context};
+use log::{error, info, warn};
+use message::{Response, SrvRecord};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn dns_srv_config(nameserver: SocketAddr) -> config::DnsSrv {
+        config::DnsSrv {
+            name: String::from("_edge._tcp.{node_group}.example.net"),
+            node_groups: vec![],
+            nameserver: Some(nameserver),
+            scheme: String::from("https"),
+            timeout: Duration::from_secs(2),
+            min_refresh_interval: Duration::from_secs(5),
+            max_refresh_interval: Duration::from_secs(60),
+        }
+    }
+
+    fn srv_record(priority: u16, weight: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port: 8443,
+            target: String::from(target),
+            ttl: 30,
+        }
+    }
+
+    fn weights(edge_nodes: &[EdgeNode]) -> Vec<(&str, u32)> {
+        edge_nodes
+            .iter()
+            .map(|en| (en.url.host_str().unwrap(), en.weight))
+            .collect()
+    }
+
+    #[test]
+    fn test_edge_nodes_of_records() {
+        let records = vec![
+            srv_record(20, 10, "backup.com"),
+            srv_record(10, 3, "alpha.com"),
+            srv_record(10, 0, "beta.com"),
+        ];
+
+        let edge_nodes = edge_nodes_of_records(&records, "std", "https");
+
+        assert_eq!(
+            vec![("alpha.com", 3), ("beta.com", 0)],
+            weights(&edge_nodes)
+        );
+        assert_eq!("https://alpha.com:8443/", edge_nodes[0].url.as_str());
+        assert_eq!("std", edge_nodes[0].group);
+
+        let records = vec![
+            srv_record(10, 0, "alpha.com"),
+            srv_record(10, 0, "beta.com"),
+        ];
+
+        assert_eq!(
+            vec![("alpha.com", 1), ("beta.com", 1)],
+            weights(&edge_nodes_of_records(&records, "std", "https"))
+        );
+
+        assert!(edge_nodes_of_records(&[srv_record(0, 0, "")], "std", "https").is_empty());
+    }
+
+    const STUB_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn stub_udp_server() -> std::net::UdpSocket {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(STUB_TIMEOUT)).unwrap();
+
+        server
+    }
+
+    /// The question of the query without its opt record.
+    fn stub_response_header(query: &[u8]) -> Vec<u8> {
+        let mut response = query[..query.len() - 11].to_vec();
+        response[2] |= 0x80;
+        response[11] = 0;
+
+        response
+    }
+
+    fn stub_srv_response(query: &[u8]) -> Vec<u8> {
+        let mut response = stub_response_header(query);
+        response[7] = 1;
+
+        // edge followed by a pointer to example.net of the question
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 33, 0, 1, 0, 0, 0, 1, 0, 13]);
+        response.extend_from_slice(&[0, 1, 0, 1, 0x01, 0xbb, 4]);
+        response.extend_from_slice(b"edge\xc0\x1b");
+
+        response
+    }
+
+    fn stub_edge_nodes() -> Vec<EdgeNode> {
+        vec![EdgeNode {
+            url: Url::parse("https://edge.example.net").unwrap(),
+            group: String::from("std"),
+            weight: 1,
+            load: None,
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_stub_dns_server() {
+        let server = stub_udp_server();
+        let resolver =
+            SrvResolver::from_config(&dns_srv_config(server.local_addr().unwrap())).unwrap();
+
+        let stub_dns_server = std::thread::spawn(move || {
+            let mut query = [0; 512];
+            let (length, client) = server.recv_from(&mut query).unwrap();
+
+            server
+                .send_to(&stub_srv_response(&query[..length]), client)
+                .unwrap();
+        });
+
+        let (edge_nodes, refresh_interval) = resolver.resolve("std").await.unwrap();
+        stub_dns_server.join().unwrap();
+
+        assert_eq!(Some(stub_edge_nodes()), edge_nodes);
+        assert_eq!(Duration::from_secs(5), refresh_interval);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_response_is_repeated_over_tcp() {
+        let server = stub_udp_server();
+        let address = server.local_addr().unwrap();
+        let tcp_server = std::net::TcpListener::bind(address).unwrap();
+        let resolver = SrvResolver::from_config(&dns_srv_config(address)).unwrap();
+
+        let stub_dns_server = std::thread::spawn(move || {
+            let mut query = [0; 512];
+            let (length, client) = server.recv_from(&mut query).unwrap();
+
+            let mut truncated = stub_response_header(&query[..length]);
+            truncated[2] |= 0x02;
+            server.send_to(&truncated, client).unwrap();
+
+            let mut stream = accept(&tcp_server);
+            let mut length = [0; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut query = vec![0; usize::from(u16::from_be_bytes(length))];
+            stream.read_exact(&mut query).unwrap();
+
+            let response = stub_srv_response(&query);
+            stream
+                .write_all(&(response.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&response).unwrap();
+        });
+
+        let (edge_nodes, _) = resolver.resolve("std").await.unwrap();
+        stub_dns_server.join().unwrap();
+
+        assert_eq!(Some(stub_edge_nodes()), edge_nodes);
+    }
+
+    fn accept(listener: &std::net::TcpListener) -> std::net::TcpStream {
+        let deadline = std::time::Instant::now() + STUB_TIMEOUT;
+        listener.set_nonblocking(true).unwrap();
+
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false).unwrap();
+                    stream.set_read_timeout(Some(STUB_TIMEOUT)).unwrap();
+
+                    return stream;
+                }
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        && std::time::Instant::now() < deadline =>
+                {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("Stub DNS server didn't get a tcp connection: {}", e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_nameserver_is_skipped() {
+        let unreachable = stub_udp_server().local_addr().unwrap();
+        let server = stub_udp_server();
+        let mut resolver = SrvResolver::from_config(&dns_srv_config(unreachable)).unwrap();
+        resolver.nameservers.push(server.local_addr().unwrap());
+
+        let stub_dns_server = std::thread::spawn(move || {
+            let mut query = [0; 512];
+            let (length, client) = server.recv_from(&mut query).unwrap();
+
+            server
+                .send_to(&stub_srv_response(&query[..length]), client)
+                .unwrap();
+        });
+
+        let (edge_nodes, _) = resolver.resolve("std").await.unwrap();
+        stub_dns_server.join().unwrap();
+
+        assert_eq!(Some(stub_edge_nodes()), edge_nodes);
+        // the next query starts with the nameserver that answered
+        assert_eq!(1, resolver.current_nameserver.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_non_existent_name_keeps_edge_nodes() {
+        let server = stub_udp_server();
+        let resolver =
+            SrvResolver::from_config(&dns_srv_config(server.local_addr().unwrap())).unwrap();
+
+        let stub_dns_server = std::thread::spawn(move || {
+            let mut query = [0; 512];
+            let (length, client) = server.recv_from(&mut query).unwrap();
+
+            let mut name_error = stub_response_header(&query[..length]);
+            name_error[3] |= 3;
+            server.send_to(&name_error, client).unwrap();
+        });
+
+        let (edge_nodes, refresh_interval) = resolver.resolve("std").await.unwrap();
+        stub_dns_server.join().unwrap();
+
+        assert_eq!(None, edge_nodes);
+        assert_eq!(resolver.min_refresh_interval, refresh_interval);
+    }
+}
+
+/// Resolves the edge nodes of the configured node groups from DNS SRV records,
+/// the records are refreshed when their TTL expires.
+pub struct DnsSrvEdgeNodeProvider {
+    edge_nodes: Arc<EdgeNodeStorage>,
+    readiness: Readiness,
+}
+
+impl EdgeNodeProvider for DnsSrvEdgeNodeProvider {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        stored_edge_nodes(&self.edge_nodes, node_group)
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+}
+
+impl DnsSrvEdgeNodeProvider {
+    pub fn new(config: &config::DnsSrv) -> anyhow::Result<Self> {
+        let resolver = Arc::new(SrvResolver::from_config(config)?);
+        let edge_nodes = Arc::new(RwLock::new(HashMap::new()));
+        let mut node_group_readiness = Vec::with_capacity(config.node_groups.len());
+
+        for node_group in &config.node_groups {
+            let (ready_signal, readiness) = Readiness::pending();
+            node_group_readiness.push(readiness);
+
+            start_refresh_edge_nodes_loop(
+                Arc::downgrade(&edge_nodes),
+                Arc::clone(&resolver),
+                node_group.clone(),
+                ready_signal,
+            );
+        }
+
+        Ok(DnsSrvEdgeNodeProvider {
+            edge_nodes,
+            readiness: Readiness::all(node_group_readiness),
+        })
+    }
+}
+
+fn start_refresh_edge_nodes_loop(
+    edge_nodes: Weak<EdgeNodeStorage>,
+    resolver: Arc<SrvResolver>,
+    node_group: String,
+    ready_signal: ReadySignal,
+) {
+    info!(
+        "Start refresh edge nodes loop for node group `{}`",
+        node_group
+    );
+
+    tokio::spawn(async move {
+        refresh_edge_nodes_loop(edge_nodes, resolver, node_group, ready_signal).await
+    });
+}
+
+async fn refresh_edge_nodes_loop(
+    edge_nodes: Weak<EdgeNodeStorage>,
+    resolver: Arc<SrvResolver>,
+    node_group: String,
+    mut ready_signal: ReadySignal,
+) {
+    loop {
+        let result = resolver.resolve(&node_group).await;
+
+        let edge_nodes = match edge_nodes.upgrade() {
+            Some(provider) => provider,
+            None => {
+                info!("Couldn't get reference to the edge node storage, ending refresh loop");
+                break;
+            }
+        };
+
+        let refresh_interval = match result {
+            Ok((None, refresh_interval)) => {
+                warn!(
+                    "DNS name of node group `{}` doesn't exist, keeping its last edge nodes",
+                    node_group
+                );
+
+                ready_signal.set_ready();
+
+                refresh_interval
+            }
+            Ok((Some(new_edge_nodes), refresh_interval)) => {
+                if *stored_edge_nodes(&edge_nodes, &node_group) != new_edge_nodes {
+                    info!(
+                        "Updating edge nodes of node group `{}` from DNS: {:?}",
+                        node_group, &new_edge_nodes
+                    );
+
+                    edge_nodes
+                        .write()
+                        .unwrap()
+                        .insert(node_group.clone(), Arc::new(new_edge_nodes));
+                }
+
+                ready_signal.set_ready();
+
+                refresh_interval
+            }
+            Err(e) => {
+                error!(
+                    "Failed to update edge nodes of node group `{}` from DNS: {:?}",
+                    node_group, e
+                );
+
+                resolver.min_refresh_interval
+            }
+        };
+
+        drop(edge_nodes);
+        time::delay_for(refresh_interval).await;
+    }
+}
+
+struct SrvResolver {
+    name: String,
+    nameservers: Vec<SocketAddr>,
+    /// Index of the nameserver that answered last
+    current_nameserver: AtomicUsize,
+    scheme: String,
+    timeout: Duration,
+    min_refresh_interval: Duration,
+    max_refresh_interval: Duration,
+}
+
+impl SrvResolver {
+    fn from_config(config: &config::DnsSrv) -> anyhow::Result<SrvResolver> {
+        let nameservers = match config.nameserver {
+            Some(nameserver) => vec![nameserver],
+            None => system_nameservers()?,
+        };
+
+        Ok(SrvResolver {
+            name: config.name.clone(),
+            nameservers,
+            current_nameserver: AtomicUsize::new(0),
+            scheme: config.scheme.clone(),
+            timeout: config.timeout,
+            min_refresh_interval: config.min_refresh_interval,
+            max_refresh_interval: config.max_refresh_interval,
+        })
+    }
+
+    /// Resolves the edge nodes of the node group and the time until they should be refreshed,
+    /// there are no edge nodes if the name doesn't exist.
+    async fn resolve(&self, node_group: &str) -> anyhow::Result<(Option<Vec<EdgeNode>>, Duration)> {
+        let name = self.name.replace("{node_group}", node_group);
+
+        let records = match self.lookup(&name).await? {
+            Some(records) => records,
+            None => return Ok((None, self.min_refresh_interval)),
+        };
+
+        let refresh_interval = records
+            .iter()
+            .map(|record| Duration::from_secs(u64::from(record.ttl)))
+            .min()
+            .unwrap_or(self.min_refresh_interval)
+            .max(self.min_refresh_interval)
+            .min(self.max_refresh_interval);
+
+        let edge_nodes = edge_nodes_of_records(&records, node_group, &self.scheme);
+
+        Ok((Some(edge_nodes), refresh_interval))
+    }
+
+    /// Queries the nameservers in turn until one of them answers,
+    /// starting with the one that answered last.
+    async fn lookup(&self, name: &str) -> anyhow::Result<Option<Vec<SrvRecord>>> {
+        let first = self.current_nameserver.load(Ordering::Relaxed);
+        let mut last_error = anyhow!("No nameserver to query for `{}`", name);
+
+        for offset in 0..self.nameservers.len() {
+            let index = (first + offset) % self.nameservers.len();
+            let nameserver = self.nameservers[index];
+
+            let result = time::timeout(self.timeout, self.query(nameserver, name))
+                .await
+                .map_err(|_| anyhow!("DNS query for `{}` at {} timed out", name, nameserver))
+                .and_then(|result| result);
+
+            let records = match result {
+                Ok(Response::Records(records)) => Some(records),
+                Ok(Response::NameError) => None,
+                Ok(Response::Truncated) => bail!("DNS response for `{}` is truncated", name),
+                Err(e) => {
+                    warn!("{:#}", e);
+                    last_error = e;
+                    continue;
+                }
+            };
+
+            self.current_nameserver.store(index, Ordering::Relaxed);
+
+            return Ok(records);
+        }
+
+        Err(last_error)
+    }
+
+    /// Queries the nameserver over udp, and over tcp if the records don't fit into a datagram.
+    async fn query(&self, nameserver: SocketAddr, name: &str) -> anyhow::Result<Response> {
+        let id = rand::random();
+        let query = message::encode_query(id, name)?;
+
+        let response = query_udp(nameserver, &query)
+            .await
+            .with_context(|| format!("Failed to query nameserver {}", nameserver))?;
+
+        let response = match message::decode_response(id, &response) {
+            Ok(Response::Truncated) => {
+                let response = query_tcp(nameserver, &query).await.with_context(|| {
+                    format!("Failed to query nameserver {} over tcp", nameserver)
+                })?;
+
+                message::decode_response(id, &response)
+            }
+            response => response,
+        };
+
+        response.with_context(|| format!("Invalid DNS response for `{}`", name))
+    }
+}
+
+async fn query_udp(nameserver: SocketAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let local_addr: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0; 8], 0).into(),
+    };
+
+    let mut socket = UdpSocket::bind(local_addr).await?;
+    socket.connect(nameserver).await?;
+    socket.send(query).await?;
+
+    let mut response = vec![0; usize::from(message::MAX_RESPONSE_SIZE)];
+    let length = socket.recv(&mut response).await?;
+    response.truncate(length);
+
+    Ok(response)
+}
+
+/// Messages over tcp are prefixed with their length, see RFC 1035 4.2.2
+async fn query_tcp(nameserver: SocketAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(nameserver).await?;
+
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(query);
+    stream.write_all(&message).await?;
+
+    let mut length = [0; 2];
+    stream.read_exact(&mut length).await?;
+
+    let mut response = vec![0; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut response).await?;
+
+    Ok(response)
+}
+
+/// Converts the SRV records with the lowest priority into edge nodes, the records with a
+/// higher priority are only used if there are no others.
+fn edge_nodes_of_records(records: &[SrvRecord], node_group: &str, scheme: &str) -> Vec<EdgeNode> {
+    // a target of `.` announces that the service isn't available
+    let available: Vec<&SrvRecord> = records.iter().filter(|r| !r.target.is_empty()).collect();
+
+    let priority = match available.iter().map(|r| r.priority).min() {
+        Some(priority) => priority,
+        None => return vec![],
+    };
+
+    let records: Vec<&SrvRecord> = available
+        .into_iter()
+        .filter(|r| r.priority == priority)
+        .collect();
+
+    // a weight of 0 only drains a target if the other targets have a weight
+    let unweighted = records.iter().all(|r| r.weight == 0);
+
+    records
+        .into_iter()
+        .filter_map(|record| {
+            let url = format!("{}://{}:{}", scheme, record.target, record.port);
+
+            match Url::parse(&url) {
+                Ok(url) => Some(EdgeNode {
+                    url,
+                    group: String::from(node_group),
+                    weight: if unweighted {
+                        1
+                    } else {
+                        u32::from(record.weight)
+                    },
+                    load: None,
+                }),
+                Err(e) => {
+                    warn!("Failed to parse url {} of SRV record: {}", url, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn system_nameservers() -> anyhow::Result<Vec<SocketAddr>> {
+    let resolv_conf = std::fs::read_to_string("/etc/resolv.conf")
+        .context("Failed to read nameservers from /etc/resolv.conf")?;
+
+    let nameservers: Vec<SocketAddr> = resolv_conf
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+
+            match (parts.next(), parts.next()) {
+                (Some("nameserver"), Some(ip)) => ip.parse::<IpAddr>().ok(),
+                _ => None,
+            }
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+
+    match nameservers.is_empty() {
+        true => Err(anyhow!("Missing nameserver in /etc/resolv.conf")),
+        false => Ok(nameservers),
+    }
+}
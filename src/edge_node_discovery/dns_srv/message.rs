@@ -0,0 +1,309 @@
+//! Minimal encoding of DNS SRV queries and decoding of their responses, see RFC 1035 and RFC 2782
+
+use anyhow::{anyhow, bail, Context};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(rcode: u8, answers: &[u8]) -> Vec<u8> {
+        let mut response = encode_query(0x1234, "_edge._tcp.std.example.net").unwrap();
+        response[2] |= 0x80;
+        response[3] |= rcode;
+        response[7] = if answers.is_empty() { 0 } else { 2 };
+        // drop the opt record of the query
+        response[11] = 0;
+        response.truncate(response.len() - 11);
+        response.extend_from_slice(answers);
+
+        response
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(0x1234, "_edge._tcp.std.example.net.").unwrap();
+
+        assert_eq!(
+            &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1],
+            &query[..12]
+        );
+        assert_eq!(
+            &b"\x05_edge\x04_tcp\x03std\x07example\x03net\x00\x00\x21\x00\x01"[..],
+            &query[12..query.len() - 11]
+        );
+
+        assert!(encode_query(1, "_edge._tcp..example.net").is_err());
+    }
+
+    #[test]
+    fn test_decode_srv_response() {
+        let answers = [
+            // compressed name of the question, srv, in, ttl 30
+            &[0xc0, 0x0c, 0, 33, 0, 1, 0, 0, 0, 30][..],
+            &[0, 25, 0, 10, 0, 5, 0x01, 0xbb, 5][..],
+            &b"alpha\x07example\x03net\x00"[..],
+            &[0xc0, 0x0c, 0, 33, 0, 1, 0, 0, 0, 20][..],
+            &[0, 13, 0, 10, 0, 20, 0x1f, 0x90, 4][..],
+            // beta followed by a pointer to example.net of the first answer
+            &b"beta\xc0\x44"[..],
+        ]
+        .concat();
+
+        let records = decode_response(0x1234, &response(0, &answers)).unwrap();
+
+        assert_eq!(
+            Response::Records(vec![
+                SrvRecord {
+                    priority: 10,
+                    weight: 5,
+                    port: 443,
+                    target: String::from("alpha.example.net"),
+                    ttl: 30,
+                },
+                SrvRecord {
+                    priority: 10,
+                    weight: 20,
+                    port: 8080,
+                    target: String::from("beta.example.net"),
+                    ttl: 20,
+                }
+            ]),
+            records
+        );
+
+        assert!(decode_response(0x4321, &response(0, &answers)).is_err());
+        assert!(decode_response(0x1234, &response(0, &answers[..40])).is_err());
+    }
+
+    #[test]
+    fn test_decode_error_response() {
+        assert_eq!(
+            Response::NameError,
+            decode_response(0x1234, &response(3, &[])).unwrap()
+        );
+        assert!(decode_response(0x1234, &response(2, &[])).is_err());
+    }
+
+    #[test]
+    fn test_decode_truncated_response() {
+        let mut truncated = response(0, &[]);
+        truncated[2] |= 0x02;
+
+        assert_eq!(
+            Response::Truncated,
+            decode_response(0x1234, &truncated).unwrap()
+        );
+    }
+}
+
+const TYPE_SRV: u16 = 33;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+const RCODE_NAME_ERROR: u16 = 3;
+
+/// Size of the udp responses announced to the nameserver via EDNS
+pub(super) const MAX_RESPONSE_SIZE: u16 = 4096;
+
+const MAX_COMPRESSION_POINTERS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub(super) struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+    pub ttl: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Response {
+    Records(Vec<SrvRecord>),
+    /// The name doesn't exist
+    NameError,
+    /// The records didn't fit into the udp response, the query has to be repeated over tcp
+    Truncated,
+}
+
+pub(super) fn encode_query(id: u16, name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(512);
+
+    for value in &[id, FLAG_RECURSION_DESIRED, 1, 0, 0, 1] {
+        query.extend_from_slice(&value.to_be_bytes());
+    }
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("Invalid label `{}` in DNS name `{}`", label, name);
+        }
+
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    // opt pseudo record of the root domain that allows responses larger than 512 bytes
+    query.push(0);
+    query.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    query.extend_from_slice(&MAX_RESPONSE_SIZE.to_be_bytes());
+    query.extend_from_slice(&[0; 6]);
+
+    Ok(query)
+}
+
+/// Decodes the SRV records of a response.
+pub(super) fn decode_response(id: u16, message: &[u8]) -> anyhow::Result<Response> {
+    let mut reader = Reader::new(message);
+
+    if reader.u16()? != id {
+        bail!("DNS response id doesn't match the query");
+    }
+
+    let flags = reader.u16()?;
+    if flags & FLAG_RESPONSE == 0 {
+        bail!("DNS message is no response");
+    }
+
+    if flags & FLAG_TRUNCATED != 0 {
+        return Ok(Response::Truncated);
+    }
+
+    match flags & 0x000f {
+        0 => {}
+        RCODE_NAME_ERROR => return Ok(Response::NameError),
+        rcode => bail!("DNS query failed with response code {}", rcode),
+    }
+
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    reader.skip(4)?;
+
+    for _ in 0..questions {
+        reader.name()?;
+        reader.skip(4)?;
+    }
+
+    let mut records = vec![];
+    for _ in 0..answers {
+        reader.name()?;
+        let record_type = reader.u16()?;
+        let class = reader.u16()?;
+        let ttl = reader.u32()?;
+        let length = usize::from(reader.u16()?);
+        let end = reader.position + length;
+
+        // answers may also contain e.g. the cname records of the name
+        if record_type == TYPE_SRV && class == CLASS_IN {
+            records.push(SrvRecord {
+                priority: reader.u16()?,
+                weight: reader.u16()?,
+                port: reader.u16()?,
+                target: reader.name()?,
+                ttl,
+            });
+        }
+
+        reader.seek(end)?;
+    }
+
+    Ok(Response::Records(records))
+}
+
+struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(message: &'a [u8]) -> Reader<'a> {
+        Reader {
+            message,
+            position: 0,
+        }
+    }
+
+    fn bytes(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .message
+            .get(self.position..self.position + length)
+            .context("Unexpected end of the DNS message")?;
+
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, length: usize) -> anyhow::Result<()> {
+        self.bytes(length).map(|_| ())
+    }
+
+    fn seek(&mut self, position: usize) -> anyhow::Result<()> {
+        if position > self.message.len() {
+            bail!("Unexpected end of the DNS message");
+        }
+
+        self.position = position;
+
+        Ok(())
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.bytes(2)?;
+
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.bytes(4)?;
+
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name, the reader continues after its first pointer.
+    fn name(&mut self) -> anyhow::Result<String> {
+        let mut labels = vec![];
+        let mut continue_at = None;
+        let mut pointers = 0;
+
+        loop {
+            let length = self.u8()?;
+
+            match length & 0xc0 {
+                0x00 if length == 0 => break,
+                0x00 => {
+                    let label = self.bytes(usize::from(length))?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                }
+                0xc0 => {
+                    let offset = usize::from(length & 0x3f) << 8 | usize::from(self.u8()?);
+
+                    pointers += 1;
+                    if pointers > MAX_COMPRESSION_POINTERS {
+                        bail!("Too many compression pointers in DNS name");
+                    }
+
+                    continue_at.get_or_insert(self.position);
+                    self.seek(offset)?;
+                }
+                _ => return Err(anyhow!("Unsupported label type in DNS name")),
+            }
+        }
+
+        if let Some(position) = continue_at {
+            self.position = position;
+        }
+
+        Ok(labels.join("."))
+    }
+}
@@ -0,0 +1,136 @@
+use super::{EdgeNode, EdgeNodeList, EdgeNodeProvider, Readiness};
+use crate::config;
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockEdgeNodeProvider;
+
+    impl EdgeNodeProvider for MockEdgeNodeProvider {
+        fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+            match node_group {
+                "std" | "origin-shield" => Arc::new(vec![EdgeNode {
+                    url: Url::parse(&format!("https://{}.com", node_group)).unwrap(),
+                    group: String::from(node_group),
+                    weight: 1,
+                    load: None,
+                }]),
+                _ => Arc::new(vec![]),
+            }
+        }
+    }
+
+    fn provider(origin: Option<Url>) -> NodeGroupFallback<MockEdgeNodeProvider> {
+        let mut fallbacks = HashMap::new();
+        fallbacks.insert(
+            String::from("premium"),
+            vec![String::from("gold"), String::from("origin-shield")],
+        );
+        fallbacks.insert(String::from("gold"), vec![]);
+
+        NodeGroupFallback::new(
+            MockEdgeNodeProvider,
+            fallbacks,
+            vec![String::from("std")],
+            origin,
+        )
+    }
+
+    fn groups(edge_nodes: EdgeNodeList) -> Vec<String> {
+        edge_nodes.iter().map(|en| en.group.clone()).collect()
+    }
+
+    #[test]
+    fn test_fallback_chains() {
+        let provider = provider(None);
+
+        assert_eq!(vec!["std"], groups(provider.get_edge_nodes("std")));
+        assert_eq!(vec!["std"], groups(provider.get_edge_nodes("unknown")));
+        assert_eq!(
+            vec!["origin-shield"],
+            groups(provider.get_edge_nodes("premium"))
+        );
+        assert!(provider.get_edge_nodes("gold").is_empty());
+    }
+
+    #[test]
+    fn test_origin_fallback() {
+        let provider = provider(Some(Url::parse("https://origin.com").unwrap()));
+
+        assert_eq!(vec!["std"], groups(provider.get_edge_nodes("unknown")));
+        assert_eq!(
+            "https://origin.com/",
+            provider.get_edge_nodes("gold")[0].url.as_str()
+        );
+    }
+}
+
+/// Falls back to other node groups if a node group has no edge nodes.
+///
+/// Node groups without a configured fallback chain use the default fallbacks,
+/// if none of the groups has edge nodes the origin is used, if configured.
+pub struct NodeGroupFallback<T: EdgeNodeProvider> {
+    edge_node_provider: T,
+    fallbacks: HashMap<String, Vec<String>>,
+    default_fallbacks: Vec<String>,
+    origin: EdgeNodeList,
+}
+
+impl<T: EdgeNodeProvider> NodeGroupFallback<T> {
+    pub fn new(
+        edge_node_provider: T,
+        fallbacks: HashMap<String, Vec<String>>,
+        default_fallbacks: Vec<String>,
+        origin: Option<Url>,
+    ) -> NodeGroupFallback<T> {
+        let origin = origin
+            .map(|url| EdgeNode {
+                url,
+                group: String::from("origin"),
+                weight: 1,
+                load: None,
+            })
+            .into_iter()
+            .collect();
+
+        NodeGroupFallback {
+            edge_node_provider,
+            fallbacks,
+            default_fallbacks,
+            origin: Arc::new(origin),
+        }
+    }
+
+    pub fn from_config(edge_node_provider: T, config: &config::NodeGroups) -> NodeGroupFallback<T> {
+        NodeGroupFallback::new(
+            edge_node_provider,
+            config.fallbacks.clone(),
+            config.default_fallbacks.clone(),
+            config.origin_fallback.clone(),
+        )
+    }
+}
+
+impl<T: EdgeNodeProvider> EdgeNodeProvider for NodeGroupFallback<T> {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        let fallbacks = self
+            .fallbacks
+            .get(node_group)
+            .unwrap_or(&self.default_fallbacks);
+
+        iter::once(node_group)
+            .chain(fallbacks.iter().map(String::as_str))
+            .map(|group| self.edge_node_provider.get_edge_nodes(group))
+            .find(|edge_nodes| !edge_nodes.is_empty())
+            .unwrap_or_else(|| Arc::clone(&self.origin))
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.edge_node_provider.readiness()
+    }
+}
@@ -0,0 +1,196 @@
+use super::{
+    group_edge_nodes, stored_edge_nodes, EdgeNode, EdgeNodeList, EdgeNodeProvider, EdgeNodeStorage,
+    DEFAULT_WEIGHT,
+};
+use crate::config;
+use anyhow::Context;
+use log::{error, info};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::{fs, time};
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml_edge_nodes() {
+        let edge_nodes = parse_edge_nodes(
+            r#"
+            - url: https://edge-1.com
+              group: std
+              weight: 3
+            - url: https://edge-2.com
+              group: premium
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                EdgeNode {
+                    url: Url::parse("https://edge-1.com").unwrap(),
+                    group: String::from("std"),
+                    weight: 3,
+                    load: None,
+                },
+                EdgeNode {
+                    url: Url::parse("https://edge-2.com").unwrap(),
+                    group: String::from("premium"),
+                    weight: 1,
+                    load: None,
+                }
+            ],
+            edge_nodes
+        );
+    }
+
+    #[test]
+    fn test_parse_json_edge_nodes() {
+        let edge_nodes =
+            parse_edge_nodes(r#"[{"url": "https://edge-1.com", "group": "std", "weight": 0}]"#)
+                .unwrap();
+
+        assert_eq!(1, edge_nodes.len());
+        assert_eq!(0, edge_nodes[0].weight);
+
+        assert!(parse_edge_nodes(r#"[{"url": "edge-1", "group": "std"}]"#).is_err());
+    }
+}
+
+/// Provides the edge nodes listed in a YAML or JSON file, the file is reloaded when it changes.
+pub struct FileEdgeNodeProvider {
+    edge_nodes: Arc<EdgeNodeStorage>,
+}
+
+impl EdgeNodeProvider for FileEdgeNodeProvider {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        stored_edge_nodes(&self.edge_nodes, node_group)
+    }
+}
+
+impl FileEdgeNodeProvider {
+    pub fn new(config: &config::EdgeNodeFile) -> anyhow::Result<Self> {
+        let modified = std::fs::metadata(&config.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        let content = std::fs::read_to_string(&config.path)
+            .with_context(|| format!("Failed to read edge node file {}", config.path.display()))?;
+
+        let edge_nodes = Arc::new(RwLock::new(group_edge_nodes(parse_edge_nodes(&content)?)));
+
+        let provider = FileEdgeNodeProvider {
+            edge_nodes: Arc::clone(&edge_nodes),
+        };
+
+        start_reload_edge_nodes_loop(
+            Arc::downgrade(&edge_nodes),
+            config.path.clone(),
+            config.reload_interval,
+            modified,
+        );
+
+        Ok(provider)
+    }
+}
+
+fn start_reload_edge_nodes_loop(
+    edge_nodes: Weak<EdgeNodeStorage>,
+    path: PathBuf,
+    reload_interval: Duration,
+    modified: Option<SystemTime>,
+) {
+    info!("Start reload edge nodes loop for {}", path.display());
+
+    tokio::spawn(async move {
+        reload_edge_nodes_loop(edge_nodes, path, reload_interval, modified).await
+    });
+}
+
+async fn reload_edge_nodes_loop(
+    edge_nodes: Weak<EdgeNodeStorage>,
+    path: PathBuf,
+    reload_interval: Duration,
+    mut modified: Option<SystemTime>,
+) {
+    let mut interval = time::interval(reload_interval);
+
+    loop {
+        interval.tick().await;
+
+        let edge_nodes = match edge_nodes.upgrade() {
+            Some(provider) => provider,
+            None => {
+                info!("Couldn't get reference to the edge node storage, ending reload loop");
+                break;
+            }
+        };
+
+        let current_modified = match fs::metadata(&path).await.and_then(|m| m.modified()) {
+            Ok(current_modified) => Some(current_modified),
+            Err(e) => {
+                error!("Failed to check edge node file {}: {:?}", path.display(), e);
+                continue;
+            }
+        };
+
+        if current_modified == modified {
+            continue;
+        }
+
+        // the previous edge nodes are kept until the file is valid again
+        match read_edge_nodes(&path).await {
+            Ok(new_edge_nodes) => {
+                info!("Reloaded edge nodes from file: {:?}", &new_edge_nodes);
+
+                *edge_nodes.write().unwrap() = group_edge_nodes(new_edge_nodes);
+                modified = current_modified;
+            }
+            Err(e) => {
+                error!("Failed to reload edge nodes from file: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn read_edge_nodes(path: &Path) -> anyhow::Result<Vec<EdgeNode>> {
+    let content = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read edge node file {}", path.display()))?;
+
+    parse_edge_nodes(&content)
+}
+
+/// Parses a list of edge nodes, JSON is accepted as it is a subset of YAML.
+fn parse_edge_nodes(content: &str) -> anyhow::Result<Vec<EdgeNode>> {
+    let entries: Vec<EdgeNodeEntry> =
+        serde_yaml::from_str(content).context("Failed to parse edge node file")?;
+
+    let edge_nodes = entries
+        .into_iter()
+        .map(|entry| EdgeNode {
+            url: entry.url,
+            group: entry.group,
+            weight: entry.weight,
+            load: None,
+        })
+        .collect();
+
+    Ok(edge_nodes)
+}
+
+#[derive(Debug, Deserialize)]
+struct EdgeNodeEntry {
+    url: Url,
+    group: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
@@ -0,0 +1,373 @@
+use super::{EdgeNode, EdgeNodeList, EdgeNodeProvider, Readiness};
+use crate::config;
+use futures::future::join_all;
+use log::{info, warn};
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+use tokio::time;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Instant;
+
+    struct MockEdgeNodeProvider(EdgeNodeList);
+
+    impl MockEdgeNodeProvider {
+        fn new() -> MockEdgeNodeProvider {
+            let edge_nodes = ["https://alpha.com", "https://beta.com"]
+                .iter()
+                .map(|url| EdgeNode {
+                    url: Url::parse(url).unwrap(),
+                    group: String::from("std"),
+                    weight: 1,
+                    load: None,
+                })
+                .collect();
+
+            MockEdgeNodeProvider(Arc::new(edge_nodes))
+        }
+    }
+
+    impl EdgeNodeProvider for MockEdgeNodeProvider {
+        fn get_edge_nodes(&self, _node_group: &str) -> EdgeNodeList {
+            Arc::clone(&self.0)
+        }
+    }
+
+    fn hosts(edge_nodes: EdgeNodeList) -> Vec<String> {
+        edge_nodes
+            .iter()
+            .map(|en| en.url.host_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_unhealthy_edge_nodes_are_removed() {
+        let provider = HealthCheckedEdgeNodeProvider {
+            health: Arc::new(EdgeNodeHealth::new(MockEdgeNodeProvider::new(), 2)),
+        };
+        let alpha = Url::parse("https://alpha.com").unwrap();
+        let beta = Url::parse("https://beta.com").unwrap();
+
+        assert!(provider.health.probed_urls().is_empty());
+        assert_eq!(
+            vec!["alpha.com", "beta.com"],
+            hosts(provider.get_edge_nodes("std"))
+        );
+        assert_eq!(2, provider.health.probed_urls().len());
+
+        provider
+            .health
+            .record_probe_results(vec![(alpha.clone(), false), (beta.clone(), true)]);
+        assert_eq!(
+            vec!["alpha.com", "beta.com"],
+            hosts(provider.get_edge_nodes("std"))
+        );
+
+        provider
+            .health
+            .record_probe_results(vec![(alpha.clone(), false), (beta.clone(), true)]);
+        assert_eq!(vec!["beta.com"], hosts(provider.get_edge_nodes("std")));
+
+        // the healthy edge nodes are only filtered again once their health changes
+        let healthy = provider.get_edge_nodes("std");
+        assert!(Arc::ptr_eq(&healthy, &provider.get_edge_nodes("std")));
+        provider
+            .health
+            .record_probe_results(vec![(alpha.clone(), false), (beta.clone(), true)]);
+        assert!(Arc::ptr_eq(&healthy, &provider.get_edge_nodes("std")));
+
+        provider
+            .health
+            .record_probe_results(vec![(alpha, true), (beta, true)]);
+        assert_eq!(
+            vec!["alpha.com", "beta.com"],
+            hosts(provider.get_edge_nodes("std"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let edge_node =
+            Url::parse(&format!("http://{}/edge/", listener.local_addr().unwrap())).unwrap();
+
+        let mock_edge_node = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let mut stream = accept(&listener);
+                let mut request = [0; 1024];
+                let length = stream.read(&mut request).unwrap();
+
+                let status = if request[..length].starts_with(b"GET /health ") {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+
+                write!(stream, "HTTP/1.1 {}\r\nConnection: close\r\n\r\n", status).unwrap();
+            }
+        });
+
+        let client = Client::new();
+        let timeout = Duration::from_secs(2);
+
+        assert!(probe(&client, &edge_node, "/health", timeout).await);
+        assert!(!probe(&client, &edge_node, "health", timeout).await);
+
+        mock_edge_node.join().unwrap();
+    }
+
+    fn accept(listener: &TcpListener) -> TcpStream {
+        let timeout = Duration::from_secs(5);
+        let deadline = Instant::now() + timeout;
+        listener.set_nonblocking(true).unwrap();
+
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false).unwrap();
+                    stream.set_read_timeout(Some(timeout)).unwrap();
+
+                    return stream;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("Mock edge node wasn't probed: {}", e),
+            }
+        }
+    }
+}
+
+/// Removes edge nodes that fail a number of consecutive probes until they pass a probe again.
+///
+/// The edge nodes of the node groups that were requested at least once are probed.
+pub struct HealthCheckedEdgeNodeProvider<T: EdgeNodeProvider> {
+    health: Arc<EdgeNodeHealth<T>>,
+}
+
+impl<T: EdgeNodeProvider + 'static> HealthCheckedEdgeNodeProvider<T> {
+    pub fn new(
+        http_client: Client,
+        edge_node_provider: T,
+        config: &config::HealthCheck,
+    ) -> HealthCheckedEdgeNodeProvider<T> {
+        let health = Arc::new(EdgeNodeHealth::new(
+            edge_node_provider,
+            config.unhealthy_threshold,
+        ));
+
+        start_probe_edge_nodes_loop(
+            Arc::downgrade(&health),
+            http_client,
+            config.path.clone(),
+            config.interval,
+            config.timeout,
+        );
+
+        HealthCheckedEdgeNodeProvider { health }
+    }
+}
+
+impl<T: EdgeNodeProvider> EdgeNodeProvider for HealthCheckedEdgeNodeProvider<T> {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        self.health.healthy_edge_nodes(node_group)
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.health.edge_node_provider.readiness()
+    }
+}
+
+fn start_probe_edge_nodes_loop<T: EdgeNodeProvider + 'static>(
+    health: Weak<EdgeNodeHealth<T>>,
+    http_client: Client,
+    path: String,
+    probe_interval: Duration,
+    timeout: Duration,
+) {
+    info!("Start probe edge nodes loop");
+
+    tokio::spawn(async move {
+        probe_edge_nodes_loop(health, http_client, path, probe_interval, timeout).await
+    });
+}
+
+async fn probe_edge_nodes_loop<T: EdgeNodeProvider>(
+    health: Weak<EdgeNodeHealth<T>>,
+    http_client: Client,
+    path: String,
+    probe_interval: Duration,
+    timeout: Duration,
+) {
+    let mut interval = time::interval(probe_interval);
+
+    loop {
+        interval.tick().await;
+
+        let urls = match health.upgrade() {
+            Some(health) => health.probed_urls(),
+            None => {
+                info!("Couldn't get reference to the edge node health, ending probe loop");
+                break;
+            }
+        };
+
+        let http_client = &http_client;
+        let path = &path;
+
+        let results = join_all(urls.into_iter().map(|url| async move {
+            let healthy = probe(http_client, &url, path, timeout).await;
+
+            (url, healthy)
+        }))
+        .await;
+
+        if let Some(health) = health.upgrade() {
+            health.record_probe_results(results);
+        }
+    }
+}
+
+async fn probe(http_client: &Client, url: &Url, path: &str, timeout: Duration) -> bool {
+    let probe_url = match url.join(path) {
+        Ok(probe_url) => probe_url,
+        Err(e) => {
+            warn!("Failed to build probe url of edge node {}: {}", url, e);
+            return false;
+        }
+    };
+
+    match http_client.get(probe_url).timeout(timeout).send().await {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+struct EdgeNodeHealth<T: EdgeNodeProvider> {
+    edge_node_provider: T,
+    unhealthy_threshold: u32,
+    node_groups: RwLock<HashSet<String>>,
+    /// Consecutive failed probes of the probed edge nodes
+    failures: RwLock<HashMap<Url, u32>>,
+    /// Changes whenever an edge node becomes healthy or unhealthy
+    generation: AtomicU64,
+    /// Healthy edge nodes per node group, with the edge nodes and the generation they are of
+    healthy: Mutex<HashMap<String, (EdgeNodeList, u64, EdgeNodeList)>>,
+}
+
+impl<T: EdgeNodeProvider> EdgeNodeHealth<T> {
+    fn new(edge_node_provider: T, unhealthy_threshold: u32) -> EdgeNodeHealth<T> {
+        EdgeNodeHealth {
+            edge_node_provider,
+            unhealthy_threshold: unhealthy_threshold.max(1),
+            node_groups: RwLock::new(HashSet::new()),
+            failures: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            healthy: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn healthy_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        if !self.node_groups.read().unwrap().contains(node_group) {
+            self.node_groups
+                .write()
+                .unwrap()
+                .insert(String::from(node_group));
+        }
+
+        let edge_nodes = self.edge_node_provider.get_edge_nodes(node_group);
+        let failures = self.failures.read().unwrap();
+        let generation = self.generation.load(Ordering::Relaxed);
+        let mut healthy = self.healthy.lock().unwrap();
+
+        // the edge node provider hands out the same list until the edge nodes change
+        if let Some((cached_edge_nodes, cached_generation, healthy_edge_nodes)) =
+            healthy.get(node_group)
+        {
+            if Arc::ptr_eq(cached_edge_nodes, &edge_nodes) && *cached_generation == generation {
+                return Arc::clone(healthy_edge_nodes);
+            }
+        }
+
+        // edge nodes that weren't probed yet are healthy
+        let is_healthy = |edge_node: &EdgeNode| {
+            failures.get(&edge_node.url).copied().unwrap_or(0) < self.unhealthy_threshold
+        };
+
+        let healthy_edge_nodes = match edge_nodes.iter().all(is_healthy) {
+            true => Arc::clone(&edge_nodes),
+            false => Arc::new(
+                edge_nodes
+                    .iter()
+                    .filter(|en| is_healthy(en))
+                    .cloned()
+                    .collect(),
+            ),
+        };
+
+        healthy.insert(
+            String::from(node_group),
+            (edge_nodes, generation, Arc::clone(&healthy_edge_nodes)),
+        );
+
+        healthy_edge_nodes
+    }
+
+    fn probed_urls(&self) -> Vec<Url> {
+        let node_groups: Vec<String> = self.node_groups.read().unwrap().iter().cloned().collect();
+        let mut urls = HashSet::new();
+
+        for node_group in node_groups {
+            let edge_nodes = self.edge_node_provider.get_edge_nodes(&node_group);
+            urls.extend(edge_nodes.iter().map(|en| en.url.clone()));
+        }
+
+        urls.into_iter().collect()
+    }
+
+    fn record_probe_results(&self, results: Vec<(Url, bool)>) {
+        let mut failures = self.failures.write().unwrap();
+
+        // edge nodes that weren't probed are no longer discovered and are forgotten
+        let mut probed = HashMap::with_capacity(results.len());
+
+        for (url, healthy) in results {
+            let previous = failures.get(&url).copied().unwrap_or(0);
+            let current = if healthy {
+                0
+            } else {
+                previous.saturating_add(1)
+            };
+
+            if current == self.unhealthy_threshold {
+                warn!("Removing edge node {} after {} failed probes", url, current);
+            } else if previous >= self.unhealthy_threshold && healthy {
+                info!("Edge node {} passed a probe again", url);
+            }
+
+            probed.insert(url, current);
+        }
+
+        let unhealthy = |failures: &HashMap<Url, u32>| -> HashSet<Url> {
+            failures
+                .iter()
+                .filter(|(_, failures)| **failures >= self.unhealthy_threshold)
+                .map(|(url, _)| url.clone())
+                .collect()
+        };
+
+        if unhealthy(&failures) != unhealthy(&probed) {
+            self.generation.fetch_add(1, Ordering::Relaxed);
+        }
+
+        *failures = probed;
+    }
+}
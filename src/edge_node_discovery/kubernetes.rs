@@ -0,0 +1,690 @@
+use super::readiness::ReadySignal;
+use super::{
+    group_edge_nodes, stored_edge_nodes, EdgeNode, EdgeNodeList, EdgeNodeProvider, EdgeNodeStorage,
+    Readiness, DEFAULT_WEIGHT,
+};
+use crate::config;
+use anyhow::{anyhow, Context};
+use log::{error, info, warn};
+use reqwest::{Certificate, Client, Response};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tokio::{fs, time};
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn kubernetes_config(api_url: &str) -> config::Kubernetes {
+        config::Kubernetes {
+            api_url: Url::parse(api_url).unwrap(),
+            namespace: Some(String::from("streaming")),
+            service: String::from("edge"),
+            port_name: Some(String::from("https")),
+            service_account_path: PathBuf::from("/nonexistent"),
+            url_template: String::from("https://{address}:{port}"),
+            keys: Default::default(),
+            retry_interval: Duration::from_secs(1),
+            watch_timeout: Duration::from_secs(290),
+        }
+    }
+
+    fn endpoint_slice(name: &str, resource_version: &str) -> String {
+        format!(
+            r#"{{
+                "metadata": {{
+                    "name": "{}",
+                    "resourceVersion": "{}",
+                    "labels": {{"grosp/node-group": "std", "kubernetes.io/service-name": "edge"}},
+                    "annotations": {{"grosp/weight": "2"}}
+                }},
+                "ports": [{{"name": "metrics", "port": 9100}}, {{"name": "https", "port": 8443}}],
+                "endpoints": [
+                    {{"addresses": ["10.0.0.1"], "conditions": {{"ready": true}}}},
+                    {{"addresses": ["10.0.0.2"], "conditions": {{"ready": false}}}},
+                    {{"addresses": ["fd00::3"], "hostname": "edge-3"}}
+                ]
+            }}"#,
+            name, resource_version
+        )
+    }
+
+    fn hosts(slices: &EndpointSlices) -> Vec<String> {
+        let mut hosts: Vec<String> = slices
+            .edge_nodes()
+            .iter()
+            .map(|en| format!("{}:{}", en.url.host_str().unwrap(), en.weight))
+            .collect();
+        hosts.sort();
+
+        hosts
+    }
+
+    #[test]
+    fn test_endpoint_slice_conversion() {
+        let conversion = EdgeNodeConversion::from_config(&kubernetes_config("http://api"));
+        let mut slice: EndpointSlice =
+            serde_json::from_str(&endpoint_slice("edge-a", "1")).unwrap();
+
+        let edge_nodes = conversion.edge_nodes(&slice);
+
+        assert_eq!(
+            vec!["https://10.0.0.1:8443/", "https://[fd00::3]:8443/"],
+            edge_nodes
+                .iter()
+                .map(|en| en.url.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert!(edge_nodes
+            .iter()
+            .all(|en| en.group == "std" && en.weight == 2));
+
+        slice.metadata.annotations = Some(
+            vec![(
+                String::from("grosp/edge-url"),
+                String::from("https://{hostname}.edge.com"),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        assert_eq!(
+            "https://edge-3.edge.com/",
+            conversion.edge_nodes(&slice)[1].url.as_str()
+        );
+
+        slice.metadata.labels = None;
+        assert!(conversion.edge_nodes(&slice).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_and_watch_with_mock_api_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let list = format!(
+            r#"{{"metadata": {{"resourceVersion": "10"}}, "items": [{}]}}"#,
+            endpoint_slice("edge-a", "9")
+        );
+        let events = [
+            format!(
+                r#"{{"type": "ADDED", "object": {}}}"#,
+                endpoint_slice("edge-b", "11").replace('\n', "")
+            ),
+            String::from(
+                r#"{"type": "DELETED", "object": {"metadata": {"name": "edge-a", "resourceVersion": "12"}}}"#,
+            ),
+            String::from(
+                r#"{"type": "BOOKMARK", "object": {"metadata": {"resourceVersion": "13"}}}"#,
+            ),
+        ]
+        .join("\n");
+
+        let mock_api_server = std::thread::spawn(move || {
+            let mut paths = vec![];
+
+            for body in &[list, events] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 2048];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]).into_owned();
+                paths.push(request.split(' ').nth(1).unwrap().to_string());
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n{}\n",
+                    body
+                )
+                .unwrap();
+            }
+
+            paths
+        });
+
+        let config = kubernetes_config(&api_url);
+        let api = EndpointSliceApi::from_config(&config).unwrap();
+        let mut slices = EndpointSlices::new(EdgeNodeConversion::from_config(&config));
+
+        slices.reset(api.list().await.unwrap());
+        assert_eq!(vec!["10.0.0.1:2", "[fd00::3]:2"], hosts(&slices));
+
+        let mut watch = api
+            .watch(slices.resource_version.as_ref().unwrap())
+            .await
+            .unwrap();
+        let mut changes = vec![];
+        while let Some(event) = watch.next_event().await.unwrap() {
+            changes.push(slices.apply(event).unwrap());
+        }
+
+        assert_eq!(vec![true, true, false], changes);
+        assert_eq!(Some("13"), slices.resource_version.as_deref());
+        assert_eq!(2, slices.edge_nodes().len());
+
+        let paths = mock_api_server.join().unwrap();
+        assert_eq!(
+            "/apis/discovery.k8s.io/v1/namespaces/streaming/endpointslices\
+             ?labelSelector=kubernetes.io%2Fservice-name%3Dedge",
+            paths[0]
+        );
+        assert!(paths[1].ends_with(
+            "&watch=true&allowWatchBookmarks=true&timeoutSeconds=290&resourceVersion=10"
+        ));
+    }
+
+    #[test]
+    fn test_watch_error_event() {
+        let mut slices = EndpointSlices::new(EdgeNodeConversion::from_config(&kubernetes_config(
+            "http://api",
+        )));
+        let event = serde_json::from_str(
+            r#"{"type": "ERROR", "object": {"code": 410, "message": "too old resource version"}}"#,
+        )
+        .unwrap();
+
+        assert!(slices.apply(event).is_err());
+    }
+}
+
+/// Watches the endpoint slices of a kubernetes service, the node group, url and weight of
+/// the edge nodes are read from the labels or annotations of the endpoint slices.
+pub struct KubernetesEdgeNodeProvider {
+    edge_nodes: Arc<EdgeNodeStorage>,
+    readiness: Readiness,
+}
+
+impl EdgeNodeProvider for KubernetesEdgeNodeProvider {
+    fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+        stored_edge_nodes(&self.edge_nodes, node_group)
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+}
+
+impl KubernetesEdgeNodeProvider {
+    pub fn new(config: &config::Kubernetes) -> anyhow::Result<Self> {
+        let api = EndpointSliceApi::from_config(config)?;
+        let slices = EndpointSlices::new(EdgeNodeConversion::from_config(config));
+        let edge_nodes = Arc::new(RwLock::new(HashMap::new()));
+        let (ready_signal, readiness) = Readiness::pending();
+
+        let provider = KubernetesEdgeNodeProvider {
+            edge_nodes: Arc::clone(&edge_nodes),
+            readiness,
+        };
+
+        start_watch_edge_nodes_loop(
+            Arc::downgrade(&edge_nodes),
+            api,
+            slices,
+            ready_signal,
+            config.retry_interval,
+        );
+
+        Ok(provider)
+    }
+}
+
+fn start_watch_edge_nodes_loop(
+    edge_nodes: Weak<EdgeNodeStorage>,
+    api: EndpointSliceApi,
+    slices: EndpointSlices,
+    ready_signal: ReadySignal,
+    retry_interval: Duration,
+) {
+    info!("Start watch edge nodes loop");
+
+    tokio::spawn(async move {
+        watch_edge_nodes_loop(edge_nodes, api, slices, ready_signal, retry_interval).await
+    });
+}
+
+async fn watch_edge_nodes_loop(
+    edge_nodes: Weak<EdgeNodeStorage>,
+    api: EndpointSliceApi,
+    mut slices: EndpointSlices,
+    mut ready_signal: ReadySignal,
+    retry_interval: Duration,
+) {
+    loop {
+        let resource_version = match &slices.resource_version {
+            Some(resource_version) => resource_version.clone(),
+            None => {
+                match api.list().await {
+                    Ok(list) => slices.reset(list),
+                    Err(e) => {
+                        error!("Failed to list endpoint slices: {:?}", e);
+                        time::delay_for(retry_interval).await;
+                        continue;
+                    }
+                }
+
+                if !store_edge_nodes(&edge_nodes, &slices) {
+                    break;
+                }
+
+                ready_signal.set_ready();
+                continue;
+            }
+        };
+
+        let mut watch = match api.watch(&resource_version).await {
+            Ok(watch) => watch,
+            Err(e) => {
+                error!("Failed to watch endpoint slices: {:?}", e);
+                slices.resource_version = None;
+                time::delay_for(retry_interval).await;
+                continue;
+            }
+        };
+
+        // the watch ends after a server side timeout and continues at the last resource version
+        loop {
+            match watch.next_event().await {
+                Ok(Some(event)) => match slices.apply(event) {
+                    Ok(true) => {
+                        if !store_edge_nodes(&edge_nodes, &slices) {
+                            return;
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!("Relisting endpoint slices after watch error: {:?}", e);
+                        slices.resource_version = None;
+                        break;
+                    }
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to watch endpoint slices: {:?}", e);
+                    time::delay_for(retry_interval).await;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Stores the edge nodes of the endpoint slices, returns false if the storage is gone.
+fn store_edge_nodes(edge_nodes: &Weak<EdgeNodeStorage>, slices: &EndpointSlices) -> bool {
+    let edge_nodes = match edge_nodes.upgrade() {
+        Some(provider) => provider,
+        None => {
+            info!("Couldn't get reference to the edge node storage, ending watch loop");
+            return false;
+        }
+    };
+
+    let new_edge_nodes = slices.edge_nodes();
+    info!("Updating edge nodes from kubernetes: {:?}", &new_edge_nodes);
+
+    *edge_nodes.write().unwrap() = group_edge_nodes(new_edge_nodes);
+
+    true
+}
+
+/// Edge nodes of the endpoint slices by name, as of the resource version
+struct EndpointSlices {
+    conversion: EdgeNodeConversion,
+    edge_nodes: HashMap<String, Vec<EdgeNode>>,
+    resource_version: Option<String>,
+}
+
+impl EndpointSlices {
+    fn new(conversion: EdgeNodeConversion) -> EndpointSlices {
+        EndpointSlices {
+            conversion,
+            edge_nodes: HashMap::new(),
+            resource_version: None,
+        }
+    }
+
+    fn reset(&mut self, list: EndpointSliceList) {
+        let conversion = &self.conversion;
+
+        self.edge_nodes = list
+            .items
+            .iter()
+            .map(|slice| (slice.metadata.name.clone(), conversion.edge_nodes(slice)))
+            .collect();
+        self.resource_version = list.metadata.resource_version;
+    }
+
+    /// Applies a watch event, returns whether the edge nodes changed.
+    fn apply(&mut self, event: WatchEvent) -> anyhow::Result<bool> {
+        let changed = match &event {
+            WatchEvent::Added(slice) | WatchEvent::Modified(slice) => {
+                let edge_nodes = self.conversion.edge_nodes(slice);
+                let previous = self
+                    .edge_nodes
+                    .insert(slice.metadata.name.clone(), edge_nodes);
+
+                previous.as_ref() != self.edge_nodes.get(&slice.metadata.name)
+            }
+            WatchEvent::Deleted(slice) => self.edge_nodes.remove(&slice.metadata.name).is_some(),
+            WatchEvent::Bookmark(_) => false,
+            WatchEvent::Error(status) => {
+                return Err(anyhow!(
+                    "Watch failed with {:?}: {}",
+                    status.code,
+                    status.message.as_deref().unwrap_or_default()
+                ))
+            }
+        };
+
+        if let WatchEvent::Added(slice)
+        | WatchEvent::Modified(slice)
+        | WatchEvent::Deleted(slice)
+        | WatchEvent::Bookmark(slice) = event
+        {
+            if slice.metadata.resource_version.is_some() {
+                self.resource_version = slice.metadata.resource_version;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    fn edge_nodes(&self) -> Vec<EdgeNode> {
+        self.edge_nodes.values().flatten().cloned().collect()
+    }
+}
+
+struct EdgeNodeConversion {
+    keys: config::KubernetesKeys,
+    url_template: String,
+    port_name: Option<String>,
+}
+
+impl EdgeNodeConversion {
+    fn from_config(config: &config::Kubernetes) -> EdgeNodeConversion {
+        EdgeNodeConversion {
+            keys: config.keys.clone(),
+            url_template: config.url_template.clone(),
+            port_name: config.port_name.clone(),
+        }
+    }
+
+    /// Converts the ready endpoints of the slice into edge nodes.
+    fn edge_nodes(&self, slice: &EndpointSlice) -> Vec<EdgeNode> {
+        self.convert_endpoint_slice(slice)
+            .map_err(|e| {
+                warn!(
+                    "Failed to convert endpoint slice into edge nodes; Error: {} for slice: {}",
+                    e, slice.metadata.name
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    fn convert_endpoint_slice(&self, slice: &EndpointSlice) -> Result<Vec<EdgeNode>, String> {
+        let metadata = |key: &str| {
+            let labels = slice.metadata.labels.as_ref();
+            let annotations = slice.metadata.annotations.as_ref();
+
+            labels
+                .and_then(|labels| labels.get(key))
+                .or_else(|| annotations.and_then(|annotations| annotations.get(key)))
+        };
+
+        let group = metadata(&self.keys.node_group)
+            .ok_or_else(|| format!("Missing {} label or annotation", self.keys.node_group))?;
+
+        let weight = match metadata(&self.keys.weight) {
+            Some(weight) => weight
+                .parse()
+                .map_err(|_| format!("Failed to parse {}", self.keys.weight))?,
+            None => DEFAULT_WEIGHT,
+        };
+
+        let url_template = metadata(&self.keys.url).unwrap_or(&self.url_template);
+
+        let port = slice
+            .ports
+            .iter()
+            .flatten()
+            .find(|port| self.port_name.is_none() || port.name == self.port_name)
+            .and_then(|port| port.port)
+            .ok_or_else(|| String::from("Missing port"))?;
+
+        let edge_nodes = slice
+            .endpoints
+            .iter()
+            .flatten()
+            // endpoints with an unknown readiness are ready
+            .filter(|endpoint| endpoint.conditions.ready != Some(false))
+            .filter_map(|endpoint| {
+                let address = endpoint.addresses.first()?;
+                let host = if address.contains(':') {
+                    format!("[{}]", address)
+                } else {
+                    address.clone()
+                };
+
+                let url = url_template
+                    .replace("{address}", &host)
+                    .replace("{hostname}", endpoint.hostname.as_ref().unwrap_or(&host))
+                    .replace("{port}", &port.to_string());
+
+                match Url::parse(&url) {
+                    Ok(url) => Some(EdgeNode {
+                        url,
+                        group: group.clone(),
+                        weight,
+                        load: None,
+                    }),
+                    Err(e) => {
+                        warn!("Failed to parse url {} of endpoint: {}", url, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Ok(edge_nodes)
+    }
+}
+
+/// Endpoint slice requests to the kubernetes api server,
+/// see https://kubernetes.io/docs/reference/using-api/api-concepts/#efficient-detection-of-changes
+/// Time a watch may stay silent after its timeout before the connection is considered gone
+const WATCH_IDLE_GRACE: Duration = Duration::from_secs(30);
+
+struct EndpointSliceApi {
+    http_client: Client,
+    url: Url,
+    token_path: PathBuf,
+    watch_timeout: Duration,
+}
+
+impl EndpointSliceApi {
+    fn from_config(config: &config::Kubernetes) -> anyhow::Result<EndpointSliceApi> {
+        let namespace = match &config.namespace {
+            Some(namespace) => namespace.clone(),
+            None => {
+                let path = config.service_account_path.join("namespace");
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read namespace from {}", path.display()))?
+                    .trim()
+                    .to_string()
+            }
+        };
+
+        let mut http_client = Client::builder();
+
+        // the certificate of the api server is signed by the cluster ca when running in a pod
+        let ca_path = config.service_account_path.join("ca.crt");
+        if let Ok(ca) = std::fs::read(&ca_path) {
+            http_client = http_client.add_root_certificate(
+                Certificate::from_pem(&ca).context("Failed to parse kubernetes ca")?,
+            );
+        }
+
+        let mut url = config.api_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid kubernetes api url `{}`", config.api_url))?
+            .pop_if_empty()
+            .extend(&[
+                "apis",
+                "discovery.k8s.io",
+                "v1",
+                "namespaces",
+                &namespace,
+                "endpointslices",
+            ]);
+        url.query_pairs_mut().append_pair(
+            "labelSelector",
+            &format!("kubernetes.io/service-name={}", config.service),
+        );
+
+        Ok(EndpointSliceApi {
+            http_client: http_client.build()?,
+            url,
+            token_path: config.service_account_path.join("token"),
+            watch_timeout: config.watch_timeout,
+        })
+    }
+
+    async fn list(&self) -> anyhow::Result<EndpointSliceList> {
+        let body = self
+            .get(self.url.clone())
+            .await?
+            .bytes()
+            .await
+            .context("Failed to retrieve endpoint slices")?;
+
+        serde_json::from_slice(&body).context("Failed to parse endpoint slices")
+    }
+
+    async fn watch(&self, resource_version: &str) -> anyhow::Result<Watch> {
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("watch", "true")
+            .append_pair("allowWatchBookmarks", "true")
+            .append_pair("timeoutSeconds", &self.watch_timeout.as_secs().to_string())
+            .append_pair("resourceVersion", resource_version);
+
+        Ok(Watch {
+            response: self.get(url).await?,
+            buffer: vec![],
+            // the api server ends the watch in time, unless the connection is gone
+            idle_timeout: self.watch_timeout + WATCH_IDLE_GRACE,
+        })
+    }
+
+    async fn get(&self, url: Url) -> anyhow::Result<Response> {
+        let mut request = self.http_client.get(url.clone());
+
+        // the service account token is rotated, outside of a pod e.g. kubectl proxy needs none
+        if let Ok(token) = fs::read_to_string(&self.token_path).await {
+            request = request.bearer_auth(token.trim());
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to query kubernetes at `{}`", url))
+    }
+}
+
+/// Stream of newline delimited watch events
+struct Watch {
+    response: Response,
+    buffer: Vec<u8>,
+    idle_timeout: Duration,
+}
+
+impl Watch {
+    async fn next_event(&mut self) -> anyhow::Result<Option<WatchEvent>> {
+        loop {
+            if let Some(position) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=position).collect();
+
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                return serde_json::from_slice(&line)
+                    .map(Some)
+                    .context("Failed to parse watch event");
+            }
+
+            let chunk = time::timeout(self.idle_timeout, self.response.chunk())
+                .await
+                .map_err(|_| anyhow!("No watch events within {:?}", self.idle_timeout))?
+                .context("Failed to read watch events")?;
+
+            match chunk {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "object", rename_all = "UPPERCASE")]
+enum WatchEvent {
+    Added(EndpointSlice),
+    Modified(EndpointSlice),
+    Deleted(EndpointSlice),
+    Bookmark(EndpointSlice),
+    Error(Status),
+}
+
+#[derive(Debug, Deserialize)]
+struct Status {
+    code: Option<u16>,
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EndpointSliceList {
+    metadata: ObjectMeta,
+    items: Vec<EndpointSlice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EndpointSlice {
+    metadata: ObjectMeta,
+    endpoints: Option<Vec<Endpoint>>,
+    ports: Option<Vec<EndpointPort>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectMeta {
+    #[serde(default)]
+    name: String,
+    resource_version: Option<String>,
+    labels: Option<HashMap<String, String>>,
+    annotations: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Endpoint {
+    addresses: Vec<String>,
+    #[serde(default)]
+    conditions: EndpointConditions,
+    hostname: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EndpointConditions {
+    ready: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EndpointPort {
+    name: Option<String>,
+    port: Option<u16>,
+}
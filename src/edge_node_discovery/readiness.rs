@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness() {
+        assert!(Readiness::ready().is_ready());
+        assert!(Readiness::ready().wait().await);
+
+        let (mut signal, readiness) = Readiness::pending();
+        assert!(!readiness.is_ready());
+
+        signal.set_ready();
+        signal.set_ready();
+        assert!(readiness.is_ready());
+        assert!(readiness.wait().await);
+
+        let (signal, readiness) = Readiness::pending();
+        drop(signal);
+        assert!(!readiness.wait().await);
+    }
+
+    #[test]
+    fn test_combined_readiness_of_ready_sources() {
+        let (_signal, pending) = Readiness::pending();
+
+        assert!(Readiness::all(vec![]).is_ready());
+        assert!(Readiness::all(vec![Readiness::ready(), Readiness::ready()]).is_ready());
+        assert!(Readiness::any(vec![pending, Readiness::ready()]).is_ready());
+    }
+}
+
+/// Tells whether the edge nodes of a provider were discovered at least once.
+#[derive(Clone)]
+pub struct Readiness(watch::Receiver<bool>);
+
+impl Readiness {
+    /// Readiness of providers that know their edge nodes from the start
+    pub fn ready() -> Readiness {
+        let (_, receiver) = watch::channel(true);
+
+        Readiness(receiver)
+    }
+
+    pub(super) fn pending() -> (ReadySignal, Readiness) {
+        let (sender, receiver) = watch::channel(false);
+
+        (ReadySignal(Some(sender)), Readiness(receiver))
+    }
+
+    /// Ready once all of the sources are ready.
+    pub(super) fn all(sources: Vec<Readiness>) -> Readiness {
+        if sources.iter().all(Readiness::is_ready) {
+            return Readiness::ready();
+        }
+
+        let (mut signal, readiness) = Readiness::pending();
+
+        tokio::spawn(async move {
+            for source in sources {
+                if !source.wait().await {
+                    return;
+                }
+            }
+
+            signal.set_ready();
+        });
+
+        readiness
+    }
+
+    /// Ready once one of the sources is ready.
+    pub(super) fn any(sources: Vec<Readiness>) -> Readiness {
+        if sources.is_empty() || sources.iter().any(Readiness::is_ready) {
+            return Readiness::ready();
+        }
+
+        let (sender, readiness) = watch::channel(false);
+        let sender = Arc::new(sender);
+
+        for source in sources {
+            let sender = Arc::clone(&sender);
+
+            tokio::spawn(async move {
+                if source.wait().await {
+                    let _ = sender.broadcast(true);
+                }
+            });
+        }
+
+        Readiness(readiness)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Waits until the provider is ready, returns false if it stopped discovering before.
+    pub async fn wait(mut self) -> bool {
+        while !self.is_ready() {
+            if self.0.recv().await.is_none() {
+                return self.is_ready();
+            }
+        }
+
+        true
+    }
+}
+
+/// Marks the readiness of a provider as ready, dropping it before leaves the provider unready.
+pub(super) struct ReadySignal(Option<watch::Sender<bool>>);
+
+impl ReadySignal {
+    pub(super) fn set_ready(&mut self) {
+        if let Some(sender) = self.0.take() {
+            let _ = sender.broadcast(true);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::EdgeNode;
+use anyhow::Context;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn edge_nodes() -> Vec<EdgeNode> {
+        vec![EdgeNode {
+            url: Url::parse("https://edge-1.com").unwrap(),
+            group: String::from("std"),
+            weight: 2,
+            load: Some(0.5),
+        }]
+    }
+
+    #[test]
+    fn test_snapshot_age() {
+        let saved_at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let content = encode(&edge_nodes(), saved_at).unwrap();
+
+        let (decoded, age) = decode(&content, saved_at + Duration::from_secs(90)).unwrap();
+
+        assert_eq!(edge_nodes(), decoded);
+        assert_eq!(Duration::from_secs(90), age);
+    }
+
+    #[tokio::test]
+    async fn test_store_and_load() {
+        let path = std::env::temp_dir().join(format!("edge-node-snapshot-{}", std::process::id()));
+        let mut snapshot = Snapshot::new(path.clone());
+
+        assert!(snapshot.load().is_err());
+
+        snapshot.store(&edge_nodes(), true).await;
+        let (loaded, age) = snapshot.load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(edge_nodes(), loaded);
+        assert!(age < Duration::from_secs(5));
+    }
+}
+
+/// Unchanged edge nodes are written again after this interval, so the age of the snapshot
+/// reflects the last successful discovery
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Copy of the last discovered edge nodes on disk, to be used after a restart during an
+/// outage of the discovery
+pub(super) struct Snapshot {
+    path: PathBuf,
+    written_at: Option<Instant>,
+}
+
+impl Snapshot {
+    pub(super) fn new(path: PathBuf) -> Snapshot {
+        Snapshot {
+            path,
+            written_at: None,
+        }
+    }
+
+    /// Reads the edge nodes of the snapshot and the time since they were discovered.
+    pub(super) fn load(&self) -> anyhow::Result<(Vec<EdgeNode>, Duration)> {
+        let content = std::fs::read(&self.path)
+            .with_context(|| format!("Failed to read snapshot {}", self.path.display()))?;
+
+        decode(&content, SystemTime::now())
+    }
+
+    /// Writes the edge nodes if they changed or the snapshot needs to be refreshed.
+    pub(super) async fn store(&mut self, edge_nodes: &[EdgeNode], changed: bool) {
+        let refresh = match self.written_at {
+            Some(written_at) => written_at.elapsed() >= REFRESH_INTERVAL,
+            None => true,
+        };
+
+        if !changed && !refresh {
+            return;
+        }
+
+        match self.write(edge_nodes).await {
+            Ok(()) => {
+                if changed {
+                    info!("Saved edge nodes to snapshot {}", self.path.display());
+                }
+
+                self.written_at = Some(Instant::now());
+            }
+            Err(e) => error!("Failed to save edge node snapshot: {:?}", e),
+        }
+    }
+
+    async fn write(&self, edge_nodes: &[EdgeNode]) -> anyhow::Result<()> {
+        let content = encode(edge_nodes, SystemTime::now())?;
+
+        // the snapshot is replaced atomically, a crash never leaves a partial snapshot behind
+        let temp_path = self.path.with_extension("tmp");
+
+        fs::write(&temp_path, content)
+            .await
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace snapshot {}", self.path.display()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotContent {
+    /// Unix timestamp of the discovery of the edge nodes
+    saved_at: u64,
+    edge_nodes: Vec<EdgeNode>,
+}
+
+fn encode(edge_nodes: &[EdgeNode], saved_at: SystemTime) -> anyhow::Result<Vec<u8>> {
+    let content = SnapshotContent {
+        saved_at: saved_at.duration_since(UNIX_EPOCH)?.as_secs(),
+        edge_nodes: edge_nodes.to_vec(),
+    };
+
+    Ok(serde_json::to_vec(&content)?)
+}
+
+fn decode(content: &[u8], now: SystemTime) -> anyhow::Result<(Vec<EdgeNode>, Duration)> {
+    let content: SnapshotContent =
+        serde_json::from_slice(content).context("Failed to parse snapshot")?;
+
+    let saved_at = UNIX_EPOCH + Duration::from_secs(content.saved_at);
+    let age = now.duration_since(saved_at).unwrap_or_default();
+
+    Ok((content.edge_nodes, age))
+}
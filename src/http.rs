@@ -1,20 +1,80 @@
+mod access_log;
 pub mod auth;
+mod client_ip;
 mod problem;
+mod upstream;
 
 use anyhow::Context;
-use hls_m3u8::MediaPlaylist;
-use log::debug;
+use hls_m3u8::{MasterPlaylist, MediaPlaylist};
+use log::warn;
 use reqwest::{Client, Url};
+use serde::Deserialize;
+use std::convert::{Infallible, TryFrom};
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Instant;
+use url::form_urlencoded;
 use warp::{filters::BoxedFilter, http::Response, reject, Filter, Rejection, Reply};
 
-use self::problem::from_anyhow;
+use self::access_log::{AccessLog, AccessLogRecord};
+use self::client_ip::client_ip;
+use self::problem::{from_anyhow, recover, Problem};
+use self::upstream::{is_timeout, CircuitOpen, Upstream};
 use crate::config::AppConfig;
-use crate::http::auth::{validate_jwt, Claims};
-use crate::playlist::PlaylistRewriter;
+use crate::edge_node_discovery::EdgeNodeProvider;
+use crate::http::auth::{validate_jwt, AuthQueryParams, Claims};
+use crate::metrics;
+use crate::playlist::{
+    Assignments, KeyUrlRewriter, LowLatencyTags, ManifestRewriter, MasterPlaylistRewriter,
+    PlaylistRewriter, RewriteContext,
+};
+use crate::trace::{self, Span, SpanKind, TraceContext};
 use hyper::http;
 use hyper::http::HeaderValue;
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invalid_playlist_query_is_extracted() {
+        let filter = or_rejection(playlist_query());
+
+        let query = warp::test::request()
+            .path("/playlist/live/stream.m3u8?jwt=token&_HLS_msn=3")
+            .filter(&filter)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!("token", query.auth_params.jwt());
+        assert_eq!(Some(3), query.blocking_reload_params.msn);
+        assert_eq!("jwt=token&_HLS_msn=3", query.raw);
+
+        let invalid = [
+            "/playlist/live/stream.m3u8",
+            "/playlist/live/stream.m3u8?_HLS_msn=3",
+            "/playlist/live/stream.m3u8?jwt=token&_HLS_msn=abc",
+        ];
+
+        for path in invalid.iter() {
+            let rejection = warp::test::request()
+                .path(path)
+                .filter(&filter)
+                .await
+                .unwrap()
+                .unwrap_err();
+
+            assert_eq!(
+                400,
+                problem::from_rejection(&rejection).status_code(),
+                "{}",
+                path
+            );
+        }
+    }
+}
+
 pub type WebResult<T> = std::result::Result<T, Rejection>;
 
 pub async fn jwt_handler(claims: Claims) -> WebResult<impl Reply> {
@@ -27,30 +87,188 @@ pub fn create_routes(
     http_client: Client,
     config: AppConfig,
     playlist_rewriter: Arc<dyn PlaylistRewriter>,
+    manifest_rewriter: Arc<dyn ManifestRewriter>,
+    edge_node_provider: Arc<dyn EdgeNodeProvider>,
 ) -> BoxedFilter<(impl Reply,)> {
-    let http_client = warp::any().map(move || http_client.clone());
+    let upstream = Arc::new(Upstream::new(http_client, &config.playlist.upstream));
+    let upstream = warp::any().map(move || Arc::clone(&upstream));
     let base_url = warp::any().map({
         let config = Arc::clone(&config);
         move || config.playlist.upstream_base_url.clone()
     });
     let playlist_rewriter = warp::any().map(move || Arc::clone(&playlist_rewriter));
+    let master_playlist_rewriter = Arc::new(
+        MasterPlaylistRewriter::new(
+            config.playlist.upstream_base_url.clone(),
+            config.http.playlist_path.clone(),
+        )
+        .with_session_key_rewriter(KeyUrlRewriter::from_config(&config.playlist.key_delivery)),
+    );
+    let master_playlist_rewriter = warp::any().map(move || Arc::clone(&master_playlist_rewriter));
+    let manifest_rewriter = warp::any().map(move || Arc::clone(&manifest_rewriter));
+    let trusted_proxies = Arc::new(config.http.trusted_proxies.clone());
+    let access_log = AccessLog::new(config.http.access_log);
+    let access_log = warp::any().map(move || access_log);
 
     let get_playlist = warp::path("playlist")
         .and(warp::get())
-        .and(validate_jwt(Arc::clone(&config)))
+        .and(claims_or_rejection(
+            Arc::clone(&config),
+            Arc::clone(&edge_node_provider),
+        ))
+        .and(or_rejection(playlist_query()))
+        .and(client_ip(Arc::clone(&trusted_proxies)))
+        .and(trace_parent())
+        .and(warp::path::full())
         .and(warp::path::tail())
-        .and(http_client)
-        .and(base_url)
+        .and(upstream.clone())
+        .and(base_url.clone())
         .and(playlist_rewriter)
+        .and(master_playlist_rewriter)
+        .and(access_log.clone())
         .and_then(get_playlist);
 
+    let get_dash_manifest = warp::path("dash")
+        .and(warp::get())
+        .and(claims_or_rejection(
+            Arc::clone(&config),
+            Arc::clone(&edge_node_provider),
+        ))
+        .and(or_rejection(warp::query::raw()))
+        .and(client_ip(Arc::clone(&trusted_proxies)))
+        .and(trace_parent())
+        .and(warp::path::full())
+        .and(warp::path::tail())
+        .and(upstream)
+        .and(base_url)
+        .and(manifest_rewriter)
+        .and(access_log)
+        .and_then(get_dash_manifest);
+
     let jwt_test = warp::path("jwt-test")
         .and(validate_jwt(Arc::clone(&config)))
         .and_then(jwt_handler);
 
     let healthz = warp::path("healthz").map(|| "🧩");
 
-    healthz.or(get_playlist).or(jwt_test).boxed()
+    let readiness = edge_node_provider.readiness();
+    let readyz = warp::path("readyz").map(move || {
+        if readiness.is_ready() {
+            warp::reply::with_status("ready", http::StatusCode::OK)
+        } else {
+            warp::reply::with_status("edge nodes unknown", http::StatusCode::SERVICE_UNAVAILABLE)
+        }
+    });
+
+    let get_metrics = warp::path("metrics").map(|| {
+        let mut response = Response::new(metrics::encode());
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+
+        response
+    });
+
+    healthz
+        .or(readyz)
+        .or(get_metrics)
+        .or(get_playlist)
+        .or(get_dash_manifest)
+        .or(jwt_test)
+        .recover(recover)
+        .with(warp::log::custom(record_request_metrics))
+        .boxed()
+}
+
+fn record_request_metrics(info: warp::log::Info) {
+    // the routes are known, arbitrary paths would blow up the number of time series
+    let route = match info.path().split('/').nth(1) {
+        Some(route @ "playlist")
+        | Some(route @ "dash")
+        | Some(route @ "jwt-test")
+        | Some(route @ "healthz")
+        | Some(route @ "readyz")
+        | Some(route @ "metrics") => route,
+        _ => "other",
+    };
+    let labels = [route, info.status().as_str()];
+
+    metrics::HTTP_REQUESTS.with_label_values(&labels).inc();
+    metrics::HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(info.elapsed().as_secs_f64());
+}
+
+/// Extracts the rejection of the filter instead of rejecting the request, so that the
+/// handler can log rejected requests as well.
+fn or_rejection<F, T>(
+    filter: F,
+) -> impl Filter<Extract = (Result<T, Rejection>,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone,
+    T: Send + 'static,
+{
+    filter
+        .map(|value: T| -> Result<T, Rejection> { Ok(value) })
+        .or_else(|rejection: Rejection| async move { Ok::<_, Infallible>((Err(rejection),)) })
+}
+
+fn claims_or_rejection(
+    config: AppConfig,
+    edge_node_provider: Arc<dyn EdgeNodeProvider>,
+) -> impl Filter<Extract = (Result<Claims, Rejection>,), Error = Infallible> + Clone {
+    or_rejection(validate_jwt_and_edge_nodes(config, edge_node_provider))
+}
+
+/// Validates the jwt and rejects the request if the node group of the viewer
+/// has no edge nodes and the config demands it.
+fn validate_jwt_and_edge_nodes(
+    config: AppConfig,
+    edge_node_provider: Arc<dyn EdgeNodeProvider>,
+) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    let fail_when_unavailable = config.node_groups.fail_when_unavailable;
+    let edge_node_provider = warp::any().map(move || Arc::clone(&edge_node_provider));
+
+    validate_jwt(config)
+        .and(edge_node_provider)
+        .and(warp::any().map(move || fail_when_unavailable))
+        .and_then(ensure_edge_nodes_available)
+}
+
+async fn ensure_edge_nodes_available(
+    claims: Claims,
+    edge_node_provider: Arc<dyn EdgeNodeProvider>,
+    fail_when_unavailable: bool,
+) -> WebResult<Claims> {
+    if fail_when_unavailable
+        && edge_node_provider
+            .get_edge_nodes(claims.node_group())
+            .is_empty()
+    {
+        warn!(
+            "No edge nodes available for node group `{}`",
+            claims.node_group()
+        );
+
+        return Err(reject::custom(Problem::new(503).with_detail(format!(
+            "No edge nodes available for node group `{}`",
+            claims.node_group()
+        ))));
+    }
+
+    Ok(claims)
+}
+
+/// The trace context of the caller, taken from the W3C `traceparent` header.
+///
+/// Invalid headers are ignored like invalid trace contexts, the trace starts over.
+fn trace_parent() -> impl Filter<Extract = (Option<TraceContext>,), Error = Infallible> + Clone {
+    warp::header::optional::<String>("traceparent")
+        .map(|traceparent: Option<String>| {
+            traceparent.and_then(|value| TraceContext::from_traceparent(&value))
+        })
+        .or_else(|_: Rejection| async { Ok::<_, Infallible>((None,)) })
 }
 
 #[derive(Debug)]
@@ -60,45 +278,208 @@ struct FetchError {
 
 impl warp::reject::Reject for FetchError {}
 
+/// Low-Latency HLS blocking playlist reload, passed through to the upstream with the query
+#[derive(Debug, Deserialize)]
+struct BlockingReloadParams {
+    #[serde(rename = "_HLS_msn")]
+    msn: Option<u64>,
+}
+
+#[derive(Debug)]
+struct PlaylistQuery {
+    auth_params: AuthQueryParams,
+    blocking_reload_params: BlockingReloadParams,
+    raw: String,
+}
+
+fn playlist_query() -> impl Filter<Extract = (PlaylistQuery,), Error = Rejection> + Clone {
+    warp::query::<AuthQueryParams>()
+        .and(warp::query::<BlockingReloadParams>())
+        .and(warp::query::raw())
+        .map(
+            |auth_params: AuthQueryParams,
+             blocking_reload_params: BlockingReloadParams,
+             raw: String| PlaylistQuery {
+                auth_params,
+                blocking_reload_params,
+                raw,
+            },
+        )
+}
+
 async fn get_playlist(
-    claims: Claims,
+    claims: Result<Claims, Rejection>,
+    query: Result<PlaylistQuery, Rejection>,
+    client_ip: Option<IpAddr>,
+    trace_parent: Option<TraceContext>,
+    path: warp::path::FullPath,
     tail: warp::path::Tail,
-    http_client: Client,
+    upstream: Arc<Upstream>,
     base_url: Url,
     playlist_rewriter: Arc<dyn PlaylistRewriter>,
+    master_playlist_rewriter: Arc<MasterPlaylistRewriter>,
+    access_log: AccessLog,
 ) -> Result<Box<dyn Reply>, Rejection> {
+    let mut record = access_log.record(client_ip, path.as_str().to_string());
+    let claims = claims.map_err(|rejection| record.rejected(rejection))?;
+    record.claims(&claims);
+    let PlaylistQuery {
+        auth_params,
+        blocking_reload_params,
+        raw: query,
+    } = query.map_err(|rejection| record.rejected(rejection))?;
+
+    let mut span =
+        trace::tracer().start_span("get_playlist", SpanKind::Server, trace_parent.as_ref());
+    span.set_attribute("node_group", claims.node_group());
+
     let upstream_playlist_url =
-        build_playlist_url(&tail, &base_url).map_err(warp::reject::custom)?;
+        build_playlist_url(&tail, &base_url, &query).map_err(|e| record.reject(e))?;
 
-    debug!("upstream playlist url: {}", upstream_playlist_url);
+    let upstream_response_body = fetch_playlist_from_upstream(
+        &upstream,
+        &upstream_playlist_url,
+        blocking_reload_params.msn.is_some(),
+        &span,
+        &mut record,
+    )
+    .await
+    .map_err(|e| record.reject(e))?;
 
-    let upstream_response_body = fetch_playlist_from_upstream(&http_client, &upstream_playlist_url)
-        .await
-        .map_err(warp::reject::custom)?;
+    let session_key = claims.session_key(client_ip);
+    let assignments = Assignments::default();
+    let context = RewriteContext {
+        node_group: claims.node_group(),
+        session_key: &session_key,
+        playlist_url: &upstream_playlist_url,
+        span: Some(&span),
+        assignments: Some(&assignments),
+    };
 
-    let response = upstream_response_body
-        .parse::<MediaPlaylist>()
-        .map(|pl| {
-            playlist_rewriter
-                .rewrite_playlist(pl, claims.node_group())
-                .to_string()
-        })
-        .unwrap_or(upstream_response_body);
+    let rewrite_start = Instant::now();
+    let (media_playlist_body, mut low_latency_tags) =
+        LowLatencyTags::extract(&upstream_response_body);
+
+    let (response, kind) = match media_playlist_body.parse::<MediaPlaylist>() {
+        Ok(pl) => {
+            let pl = playlist_rewriter.rewrite_playlist(pl, &context);
+
+            playlist_rewriter.rewrite_low_latency_tags(&pl, &mut low_latency_tags, &context);
+            master_playlist_rewriter.rewrite_rendition_reports(
+                &mut low_latency_tags,
+                &upstream_playlist_url,
+                auth_params.jwt(),
+            );
+
+            (low_latency_tags.insert_into(&pl.to_string()), "media")
+        }
+        Err(_) => match MasterPlaylist::try_from(upstream_response_body.as_str()) {
+            Ok(pl) => (
+                master_playlist_rewriter
+                    .rewrite_playlist(pl, &upstream_playlist_url, auth_params.jwt())
+                    .to_string(),
+                "master",
+            ),
+            Err(_) => (upstream_response_body, "unknown"),
+        },
+    };
+
+    metrics::REWRITE_DURATION
+        .with_label_values(&[kind])
+        .observe(rewrite_start.elapsed().as_secs_f64());
+
+    record.assignments(&assignments);
+    record.succeed();
 
     let mut response = Response::new(response);
     response.headers_mut().insert(
         http::header::CONTENT_TYPE,
         HeaderValue::from_static("application/vnd.apple.mpegurl"),
     );
+    if let Ok(request_id) = HeaderValue::from_str(record.request_id()) {
+        response
+            .headers_mut()
+            .insert(problem::REQUEST_ID, request_id);
+    }
 
     Ok(Box::new(response))
 }
 
+async fn get_dash_manifest(
+    claims: Result<Claims, Rejection>,
+    query: Result<String, Rejection>,
+    client_ip: Option<IpAddr>,
+    trace_parent: Option<TraceContext>,
+    path: warp::path::FullPath,
+    tail: warp::path::Tail,
+    upstream: Arc<Upstream>,
+    base_url: Url,
+    manifest_rewriter: Arc<dyn ManifestRewriter>,
+    access_log: AccessLog,
+) -> Result<Box<dyn Reply>, Rejection> {
+    let mut record = access_log.record(client_ip, path.as_str().to_string());
+    let claims = claims.map_err(|rejection| record.rejected(rejection))?;
+    record.claims(&claims);
+    let query = query.map_err(|rejection| record.rejected(rejection))?;
+
+    let mut span =
+        trace::tracer().start_span("get_dash_manifest", SpanKind::Server, trace_parent.as_ref());
+    span.set_attribute("node_group", claims.node_group());
+
+    let upstream_manifest_url =
+        build_playlist_url(&tail, &base_url, &query).map_err(|e| record.reject(e))?;
+
+    let upstream_response_body =
+        fetch_playlist_from_upstream(&upstream, &upstream_manifest_url, false, &span, &mut record)
+            .await
+            .map_err(|e| record.reject(e))?;
+
+    let session_key = claims.session_key(client_ip);
+    let assignments = Assignments::default();
+    let context = RewriteContext {
+        node_group: claims.node_group(),
+        session_key: &session_key,
+        playlist_url: &upstream_manifest_url,
+        span: Some(&span),
+        assignments: Some(&assignments),
+    };
+
+    let rewrite_start = Instant::now();
+    let response = manifest_rewriter
+        .rewrite_manifest(&upstream_response_body, &upstream_manifest_url, &context)
+        .unwrap_or_else(|e| {
+            warn!("Failed to rewrite dash manifest: {:?}", e);
+            upstream_response_body
+        });
+
+    metrics::REWRITE_DURATION
+        .with_label_values(&["dash"])
+        .observe(rewrite_start.elapsed().as_secs_f64());
+
+    record.assignments(&assignments);
+    record.succeed();
+
+    let mut response = Response::new(response);
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/dash+xml"),
+    );
+    if let Ok(request_id) = HeaderValue::from_str(record.request_id()) {
+        response
+            .headers_mut()
+            .insert(problem::REQUEST_ID, request_id);
+    }
+
+    Ok(Box::new(response))
+}
+
+/// The upstream url of a playlist or manifest request.
 fn build_playlist_url(
     tail: &warp::path::Tail,
     base_url: &Url,
-) -> Result<Url, impl warp::reject::Reject> {
-    base_url
+    query: &str,
+) -> Result<Url, Problem> {
+    let mut url = base_url
         .join(tail.as_str())
         .with_context(|| {
             format!(
@@ -107,31 +488,52 @@ fn build_playlist_url(
                 tail.as_str()
             )
         })
-        .map_err(|e| from_anyhow(e, 400))
+        .map_err(|e| from_anyhow(e, 400).with_detail("Invalid playlist path"))?;
+
+    // e.g. cdn tokens of the upstream and the blocking playlist reload parameters,
+    // the jwt is only meant for the spreader
+    let upstream_query: Vec<_> = form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _)| key != "jwt")
+        .collect();
+
+    if !upstream_query.is_empty() {
+        url.query_pairs_mut().extend_pairs(upstream_query);
+    }
+
+    Ok(url)
 }
 
 async fn fetch_playlist_from_upstream(
-    http_client: &Client,
+    upstream: &Upstream,
     url: &Url,
-) -> Result<String, impl warp::reject::Reject> {
-    http_client
-        .get(url.clone())
-        .send()
-        .await
-        .with_context(|| {
-            format!(
-                "Failed to retrieve playlist from upstream url `{}`",
-                url.as_str()
-            )
-        })
-        .map_err(|e| from_anyhow(e, 400))?
-        .text()
-        .await
-        .with_context(|| {
-            format!(
-                "Failed to retrieve body from upstream playlist response from url `{}`",
-                url.as_str()
-            )
-        })
-        .map_err(|e| from_anyhow(e, 400))
+    blocking: bool,
+    parent_span: &Span,
+    record: &mut AccessLogRecord,
+) -> Result<String, Problem> {
+    let mut span = parent_span.child("fetch_playlist_from_upstream", SpanKind::Client);
+    span.set_attribute("http.url", url);
+
+    let start = Instant::now();
+    let mut status = None;
+    let result = upstream.get(url, blocking, &mut span, &mut status).await;
+    let latency = start.elapsed();
+
+    metrics::UPSTREAM_REQUEST_DURATION.observe(latency.as_secs_f64());
+    record.upstream(url, status, latency);
+
+    result.map_err(|e| {
+        metrics::UPSTREAM_ERRORS.inc();
+        span.set_error(format!("{:#}", e));
+
+        // the error pages of the upstream are not passed on, they may reveal its internals
+        if e.is::<CircuitOpen>() {
+            from_anyhow(e, 503).with_detail("The upstream is unavailable")
+        } else if is_timeout(&e) {
+            from_anyhow(e, 504).with_detail("The upstream didn't respond in time")
+        } else if status == Some(404) {
+            from_anyhow(e, 404).with_detail("The playlist doesn't exist")
+        } else {
+            from_anyhow(e, 502).with_detail("The upstream failed to deliver the playlist")
+        }
+    })
 }
@@ -0,0 +1,138 @@
+use super::auth::Claims;
+use super::problem::{self, Problem};
+use crate::playlist::Assignments;
+use log::warn;
+use serde::{Serialize, Serializer};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+use url::Url;
+use warp::Rejection;
+
+/// Writes a JSON line per playlist and manifest request to stdout.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessLog {
+    enabled: bool,
+}
+
+impl AccessLog {
+    pub fn new(enabled: bool) -> AccessLog {
+        AccessLog { enabled }
+    }
+
+    /// Starts the record of a request, which is written once it is dropped.
+    pub(super) fn record(self, client_ip: Option<IpAddr>, path: String) -> AccessLogRecord {
+        AccessLogRecord {
+            access_log: self,
+            entry: Entry {
+                timestamp: SystemTime::now(),
+                request_id: problem::new_request_id(),
+                client_ip,
+                path,
+                // the request is only dropped without a status if the client went away
+                status: 499,
+                upstream_url: None,
+                upstream_status: None,
+                upstream_latency_ms: None,
+                stream_name: None,
+                node_group: None,
+                rewritten_segments: 0,
+                edge_nodes: vec![],
+            },
+        }
+    }
+
+    fn write(&self, entry: &Entry) {
+        if !self.enabled {
+            return;
+        }
+
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize access log entry: {}", e);
+                return;
+            }
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        if let Err(e) = writeln!(stdout, "{}", line) {
+            warn!("Failed to write access log entry: {}", e);
+        }
+    }
+}
+
+pub(super) struct AccessLogRecord {
+    access_log: AccessLog,
+    entry: Entry,
+}
+
+impl AccessLogRecord {
+    pub(super) fn request_id(&self) -> &str {
+        &self.entry.request_id
+    }
+
+    pub(super) fn claims(&mut self, claims: &Claims) {
+        self.entry.stream_name = Some(claims.stream_name().to_string());
+        self.entry.node_group = Some(claims.node_group().to_string());
+    }
+
+    pub(super) fn upstream(&mut self, url: &Url, status: Option<u16>, latency: Duration) {
+        self.entry.upstream_url = Some(url.clone());
+        self.entry.upstream_status = status;
+        self.entry.upstream_latency_ms = Some(latency.as_secs_f64() * 1000.0);
+    }
+
+    pub(super) fn assignments(&mut self, assignments: &Assignments) {
+        self.entry.rewritten_segments = assignments.segments();
+        self.entry.edge_nodes = assignments.edge_nodes();
+    }
+
+    pub(super) fn succeed(&mut self) {
+        self.entry.status = 200;
+    }
+
+    /// Records the status of the problem and rejects the request with it.
+    pub(super) fn reject(&mut self, problem: Problem) -> Rejection {
+        self.entry.status = problem.status_code();
+
+        warp::reject::custom(problem.with_request_id(self.entry.request_id.clone()))
+    }
+
+    /// Records the status of a request that was rejected before it was handled.
+    pub(super) fn rejected(&mut self, rejection: Rejection) -> Rejection {
+        self.reject(problem::from_rejection(&rejection))
+    }
+}
+
+impl Drop for AccessLogRecord {
+    fn drop(&mut self) {
+        self.access_log.write(&self.entry);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    #[serde(serialize_with = "serialize_timestamp")]
+    timestamp: SystemTime,
+    request_id: String,
+    client_ip: Option<IpAddr>,
+    path: String,
+    status: u16,
+    upstream_url: Option<Url>,
+    upstream_status: Option<u16>,
+    upstream_latency_ms: Option<f64>,
+    stream_name: Option<String>,
+    node_group: Option<String>,
+    rewritten_segments: usize,
+    edge_nodes: Vec<Url>,
+}
+
+fn serialize_timestamp<S: Serializer>(
+    timestamp: &SystemTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*timestamp))
+}
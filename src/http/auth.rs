@@ -1,7 +1,9 @@
 use crate::config::AppConfig;
 use crate::http::WebResult;
+use crate::metrics;
 use jsonwebtoken::{Algorithm, DecodingKey, TokenData, Validation};
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::sync::Arc;
 use thiserror::Error as ThisError;
 use warp::{reject, Filter, Rejection};
@@ -10,12 +12,21 @@ use warp::{reject, Filter, Rejection};
 pub enum Error {
     #[error("jwt was not valid")]
     JWTTokenError,
-    #[error("the stream name from doesn't match")]
+    #[error("the stream name of the jwt doesn't match the path")]
     JWTStreamNameMismatch,
 }
 
 impl warp::reject::Reject for Error {}
 
+impl Error {
+    fn reason(&self) -> &'static str {
+        match self {
+            Error::JWTTokenError => "invalid_jwt",
+            Error::JWTStreamNameMismatch => "stream_name_mismatch",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Claims {
     // Expiry epoch
@@ -24,12 +35,29 @@ pub struct Claims {
     sn: String,
     // NodeGroup name
     ng: String,
+    // Viewer session id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
 }
 
 impl Claims {
+    pub fn stream_name(&self) -> &str {
+        self.sn.as_str()
+    }
+
     pub fn node_group(&self) -> &str {
         self.ng.as_str()
     }
+
+    /// Identifies the viewer session, the session id claim if present,
+    /// otherwise the stream name together with the client ip.
+    pub fn session_key(&self, client_ip: Option<IpAddr>) -> String {
+        match (&self.sid, client_ip) {
+            (Some(sid), _) => sid.clone(),
+            (None, Some(client_ip)) => format!("{}/{}", self.sn, client_ip),
+            (None, None) => self.sn.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -37,6 +65,12 @@ pub struct AuthQueryParams {
     jwt: String,
 }
 
+impl AuthQueryParams {
+    pub fn jwt(&self) -> &str {
+        self.jwt.as_str()
+    }
+}
+
 pub fn validate_jwt(
     config: AppConfig,
 ) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
@@ -58,11 +92,13 @@ async fn validate(
         &DecodingKey::from_secret(config.playlist.jwt_validation.secret.as_ref()),
         &Validation::new(Algorithm::HS512),
     )
-    .map_err(|_| reject::custom(Error::JWTTokenError))
-    .and_then(|td| {
-        validate_stream_name(&td.claims, &config, &path)
-            .map_err(reject::custom)
-            .map(|_| td)
+    .map_err(|_| Error::JWTTokenError)
+    .and_then(|td| validate_stream_name(&td.claims, &config, &path).map(|_| td))
+    .map_err(|e| {
+        metrics::JWT_REJECTIONS
+            .with_label_values(&[e.reason()])
+            .inc();
+        reject::custom(e)
     })?;
 
     Ok(token_data.claims)
@@ -100,10 +136,12 @@ mod tests {
 
     fn config() -> std::result::Result<AppConfig, Box<dyn std::error::Error>> {
         Ok(Arc::new(config::Config {
-            consul: config::Consul {
-                base_url: Url::parse("http://localhost:8500")?,
-                update_interval: Default::default(),
-            },
+            discovery: Default::default(),
+            discovery_timeout: Default::default(),
+            consul: None,
+            health_check: None,
+            tracing: None,
+            node_groups: Default::default(),
             playlist: config::Playlist {
                 upstream_base_url: Url::parse("http://localhost")?,
                 segment_signing: config::SegmentSigning {
@@ -114,9 +152,16 @@ mod tests {
                     secret: "secret".to_string(),
                     stream_name_pattern: Regex::new(r"([^/]+)\.m3u8")?,
                 },
+                key_delivery: vec![],
+                dash: None,
+                distribution: Default::default(),
+                upstream: Default::default(),
             },
             http: config::Http {
                 socket: "[::]:23".parse()?,
+                access_log: false,
+                playlist_path: String::from("/playlist/"),
+                trusted_proxies: vec![],
             },
         }))
     }
@@ -175,6 +220,7 @@ mod tests {
                 .as_secs(),
             sn: "stream-name".to_string(),
             ng: "node-group".to_string(),
+            sid: None,
         };
 
         let token = jsonwebtoken::encode(
@@ -210,6 +256,7 @@ mod tests {
                 .as_secs(),
             sn: "stream-name".to_string(),
             ng: "node-group".to_string(),
+            sid: None,
         };
 
         let token = jsonwebtoken::encode(
@@ -245,6 +292,7 @@ mod tests {
                 .as_secs(),
             sn: "meca-foo".to_string(),
             ng: "node-group".to_string(),
+            sid: None,
         };
 
         let token = jsonwebtoken::encode(
@@ -283,6 +331,7 @@ mod tests {
                 .as_secs(),
             sn: "meca-foo".to_string(),
             ng: "node-group".to_string(),
+            sid: None,
         };
 
         let token = jsonwebtoken::encode(
@@ -0,0 +1,105 @@
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use warp::http::HeaderMap;
+use warp::{Filter, Rejection};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_ip() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let viewer: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let cases = vec![
+            // without trusted proxies the header is ignored
+            (vec![], Some("198.51.100.1"), viewer, viewer),
+            // the header of an untrusted peer is ignored
+            (trusted.clone(), Some("198.51.100.1"), viewer, viewer),
+            (trusted.clone(), Some("203.0.113.7"), proxy, viewer),
+            // spoofed entries in front of the real one are skipped
+            (
+                trusted.clone(),
+                Some("198.51.100.1, 203.0.113.7, 10.1.2.3"),
+                proxy,
+                viewer,
+            ),
+            (trusted.clone(), Some("garbage, 203.0.113.7"), proxy, viewer),
+            (trusted.clone(), Some("203.0.113.7, garbage"), proxy, proxy),
+            (
+                trusted.clone(),
+                Some("10.1.2.3"),
+                proxy,
+                "10.1.2.3".parse().unwrap(),
+            ),
+            (trusted, None, proxy, proxy),
+        ];
+
+        for (trusted, forwarded_for, remote, expected) in cases {
+            assert_eq!(
+                Some(expected),
+                resolve(forwarded_for, Some(remote), &trusted),
+                "{:?}",
+                forwarded_for
+            );
+        }
+    }
+}
+
+/// The ip of the viewer.
+///
+/// Behind proxies it's the right-most `X-Forwarded-For` entry that wasn't added by one
+/// of the `trusted_proxies`, the entries in front of it can be chosen by the viewer.
+pub fn client_ip(
+    trusted_proxies: Arc<Vec<IpNet>>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::addr::remote())
+        .map(move |headers: HeaderMap, remote_addr: Option<SocketAddr>| {
+            let forwarded_for = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(",");
+            let forwarded_for = match forwarded_for.is_empty() {
+                true => None,
+                false => Some(forwarded_for.as_str()),
+            };
+
+            resolve(
+                forwarded_for,
+                remote_addr.map(|addr| addr.ip()),
+                &trusted_proxies,
+            )
+        })
+}
+
+fn resolve(
+    forwarded_for: Option<&str>,
+    remote_ip: Option<IpAddr>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+
+    let mut client_ip = remote_ip?;
+    let hops = forwarded_for
+        .into_iter()
+        .flat_map(|value| value.rsplit(','));
+
+    for hop in hops {
+        if !is_trusted(&client_ip) {
+            break;
+        }
+
+        match hop.trim().parse() {
+            Ok(ip) => client_ip = ip,
+            Err(_) => break,
+        }
+    }
+
+    Some(client_ip)
+}
@@ -1,9 +1,95 @@
+use super::auth;
+use log::warn;
+use serde::Serialize;
+use std::convert::Infallible;
 use std::fmt;
-use warp::reject::Reject;
+use warp::http::{header, HeaderValue, Response, StatusCode};
+use warp::reject::{InvalidHeader, InvalidQuery, MethodNotAllowed, MissingHeader, Reject};
+use warp::Rejection;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_of_rejections() {
+        let rejections = vec![
+            (warp::reject::not_found(), 404),
+            (warp::reject::custom(auth::Error::JWTTokenError), 401),
+            (
+                warp::reject::custom(auth::Error::JWTStreamNameMismatch),
+                403,
+            ),
+            (warp::reject::custom(Problem::new(502)), 502),
+            (
+                warp::reject::custom(from_anyhow(anyhow::anyhow!("timeout"), 504)),
+                504,
+            ),
+        ];
+
+        for (rejection, status_code) in rejections {
+            assert_eq!(status_code, describe(&rejection).0.as_u16());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_of_header_rejections() {
+        let invalid_header = warp::test::request()
+            .header("x-count", "many")
+            .filter(&warp::header::<u32>("x-count"))
+            .await
+            .unwrap_err();
+        let missing_header = warp::test::request()
+            .filter(&warp::header::<u32>("x-count"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(StatusCode::BAD_REQUEST, describe(&invalid_header).0);
+        assert_eq!(StatusCode::BAD_REQUEST, describe(&missing_header).0);
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        let problem = Problem::new(503).with_detail("No edge nodes available");
+        let response = recover(warp::reject::custom(problem)).await.unwrap();
+
+        assert_eq!(503, response.status().as_u16());
+        assert_eq!(
+            "application/problem+json",
+            response.headers()[header::CONTENT_TYPE]
+        );
+
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+
+        assert_eq!("about:blank", body["type"]);
+        assert_eq!("Service Unavailable", body["title"]);
+        assert_eq!(503, body["status"]);
+        assert_eq!("No edge nodes available", body["detail"]);
+        assert_eq!(16, body["request_id"].as_str().unwrap().len());
+        assert_eq!(
+            body["request_id"],
+            response.headers()[REQUEST_ID].to_str().unwrap()
+        );
+
+        let problem = Problem::new(502).with_request_id("0123456789abcdef");
+        let response = recover(warp::reject::custom(problem)).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+
+        assert_eq!("0123456789abcdef", body["request_id"]);
+        assert_eq!("0123456789abcdef", response.headers()[REQUEST_ID]);
+    }
+}
+
+/// Header with the id of the request, which is logged with it
+pub(super) const REQUEST_ID: &str = "x-request-id";
 
 #[derive(Debug)]
 pub struct Problem {
     status_code: u16,
+    /// Told to the client, unlike the source
+    detail: Option<String>,
+    source: Option<anyhow::Error>,
+    request_id: Option<String>,
 }
 
 impl fmt::Display for Problem {
@@ -14,11 +100,146 @@ impl fmt::Display for Problem {
 
 impl Reject for Problem {}
 
+impl Problem {
+    pub fn new(status_code: u16) -> Problem {
+        Problem {
+            status_code,
+            detail: None,
+            source: None,
+            request_id: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Problem {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub(super) fn with_request_id(mut self, request_id: impl Into<String>) -> Problem {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+}
+
 pub fn from_anyhow(e: anyhow::Error, status_code: u16) -> Problem {
-    let _ = match e.downcast::<Problem>() {
+    let e = match e.downcast::<Problem>() {
         Ok(problem) => return problem,
         Err(e) => e,
     };
 
-    Problem { status_code }
+    Problem {
+        source: Some(e),
+        ..Problem::new(status_code)
+    }
+}
+
+/// The problem told to the client of a rejection.
+pub(super) fn from_rejection(rejection: &Rejection) -> Problem {
+    let (status, detail) = describe(rejection);
+
+    Problem {
+        detail,
+        ..Problem::new(status.as_u16())
+    }
+}
+
+pub(super) fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Responds to rejected requests with an RFC 7807 problem,
+/// see https://tools.ietf.org/html/rfc7807
+pub async fn recover(rejection: Rejection) -> Result<Response<String>, Infallible> {
+    // requests rejected by a handler keep the id they were logged with
+    let request_id = rejection
+        .find::<Problem>()
+        .and_then(|problem| problem.request_id.clone())
+        .unwrap_or_else(new_request_id);
+    let (status, detail) = describe(&rejection);
+
+    match rejection.find::<Problem>() {
+        Some(Problem {
+            source: Some(source),
+            ..
+        }) => warn!("Request {} failed: {:#}", request_id, source),
+        None if status == StatusCode::INTERNAL_SERVER_ERROR => {
+            warn!("Request {} was rejected: {:?}", request_id, rejection)
+        }
+        _ => {}
+    }
+
+    let body = Body {
+        problem_type: "about:blank",
+        title: status.canonical_reason().unwrap_or("Unknown"),
+        status: status.as_u16(),
+        detail: detail.as_deref(),
+        request_id: &request_id,
+    };
+
+    let mut response = Response::new(serde_json::to_string(&body).unwrap_or_default());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, request_id);
+    }
+
+    Ok(response)
+}
+
+fn describe(rejection: &Rejection) -> (StatusCode, Option<String>) {
+    if rejection.is_not_found() {
+        return (StatusCode::NOT_FOUND, None);
+    }
+
+    if let Some(problem) = rejection.find::<Problem>() {
+        let status =
+            StatusCode::from_u16(problem.status_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+        return (status, problem.detail.clone());
+    }
+
+    if let Some(e) = rejection.find::<auth::Error>() {
+        let status = match e {
+            auth::Error::JWTTokenError => StatusCode::UNAUTHORIZED,
+            auth::Error::JWTStreamNameMismatch => StatusCode::FORBIDDEN,
+        };
+
+        return (status, Some(e.to_string()));
+    }
+
+    if let Some(e) = rejection.find::<InvalidQuery>() {
+        return (StatusCode::BAD_REQUEST, Some(e.to_string()));
+    }
+
+    if let Some(e) = rejection.find::<InvalidHeader>() {
+        return (StatusCode::BAD_REQUEST, Some(e.to_string()));
+    }
+
+    if let Some(e) = rejection.find::<MissingHeader>() {
+        return (StatusCode::BAD_REQUEST, Some(e.to_string()));
+    }
+
+    if rejection.find::<MethodNotAllowed>().is_some() {
+        return (StatusCode::METHOD_NOT_ALLOWED, None);
+    }
+
+    (StatusCode::INTERNAL_SERVER_ERROR, None)
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'a str,
+    title: &'a str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    request_id: &'a str,
 }
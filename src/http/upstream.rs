@@ -0,0 +1,442 @@
+use crate::config;
+use crate::metrics;
+use crate::trace::Span;
+use anyhow::Context;
+use log::{info, warn};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error as ThisError;
+use tokio::time;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{SpanKind, Tracer};
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let open_duration = Duration::from_secs(10);
+        let mut circuit_breaker = CircuitBreaker::new(2, open_duration);
+        let start = Instant::now();
+
+        assert!(circuit_breaker.allow(start));
+        assert!(!circuit_breaker.record(true, start));
+        assert!(circuit_breaker.allow(start));
+        assert!(circuit_breaker.record(true, start));
+        assert!(!circuit_breaker.allow(start));
+
+        // a single trial request once the breaker was open long enough
+        let later = start + open_duration;
+        assert!(circuit_breaker.allow(later));
+        assert!(!circuit_breaker.allow(later));
+        assert!(!circuit_breaker.record(true, later));
+        assert!(!circuit_breaker.allow(later + Duration::from_secs(1)));
+
+        let recovered = later + open_duration * 2;
+        assert!(circuit_breaker.allow(recovered));
+        assert!(!circuit_breaker.record(false, recovered));
+        assert!(circuit_breaker.allow(recovered));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let backoff = Duration::from_millis(100);
+
+        for attempt in 0..5 {
+            assert!(retry_delay(backoff, attempt) <= backoff * 2u32.pow(attempt));
+        }
+
+        assert!(retry_delay(backoff, u32::MAX) <= backoff * 2u32.pow(16));
+    }
+
+    #[tokio::test]
+    async fn test_get_retries_failed_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/live/stream.m3u8",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        let mock_upstream = std::thread::spawn(move || {
+            let responses = [
+                "503 Service Unavailable",
+                "200 OK",
+                "404 Not Found",
+                "500 Internal Server Error",
+                "500 Internal Server Error",
+            ];
+
+            for status in responses.iter() {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nConnection: close\r\n\r\n#EXTM3U\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+
+        let upstream = Upstream::new(
+            Client::new(),
+            &config::Upstream {
+                max_retries: 1,
+                retry_backoff: Duration::from_millis(1),
+                circuit_breaker: config::CircuitBreaker {
+                    failure_threshold: 2,
+                    open_duration: Duration::from_secs(60),
+                },
+                ..Default::default()
+            },
+        );
+        let mut span = Tracer::disabled().start_span("test", SpanKind::Client, None);
+        let mut status = None;
+
+        let body = upstream.get(&url, false, &mut span, &mut status).await;
+        assert_eq!("#EXTM3U\n", body.unwrap());
+        assert_eq!(Some(200), status);
+
+        // missing playlists are neither retried nor count as failures
+        assert!(upstream
+            .get(&url, false, &mut span, &mut status)
+            .await
+            .is_err());
+        assert_eq!(Some(404), status);
+
+        let e = upstream
+            .get(&url, false, &mut span, &mut status)
+            .await
+            .unwrap_err();
+        assert!(e.downcast_ref::<CircuitOpen>().is_none());
+        assert_eq!(Some(500), status);
+
+        let e = upstream
+            .get(&url, false, &mut span, &mut status)
+            .await
+            .unwrap_err();
+        assert!(e.downcast_ref::<CircuitOpen>().is_some());
+
+        mock_upstream.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_retries_within_the_timeout() {
+        for blocking in [false, true].iter() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = Url::parse(&format!(
+                "http://{}/live/stream.m3u8",
+                listener.local_addr().unwrap()
+            ))
+            .unwrap();
+
+            // accepts the requests without ever responding to them
+            let mock_upstream = std::thread::spawn(move || {
+                let deadline = Instant::now() + Duration::from_secs(2);
+                let mut streams = vec![];
+                listener.set_nonblocking(true).unwrap();
+
+                while Instant::now() < deadline {
+                    match listener.accept() {
+                        Ok((stream, _)) => streams.push(stream),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                            std::thread::sleep(Duration::from_millis(10))
+                        }
+                        Err(e) => panic!("Mock upstream failed to accept: {}", e),
+                    }
+                }
+
+                streams.len()
+            });
+
+            let upstream = Upstream::new(
+                Client::new(),
+                &config::Upstream {
+                    timeout: Duration::from_millis(300),
+                    blocking_timeout: Duration::from_millis(300),
+                    max_retries: 5,
+                    retry_backoff: Duration::from_millis(1),
+                    ..Default::default()
+                },
+            );
+            let mut span = Tracer::disabled().start_span("test", SpanKind::Client, None);
+            let mut status = None;
+            let start = Instant::now();
+
+            let e = upstream
+                .get(&url, *blocking, &mut span, &mut status)
+                .await
+                .unwrap_err();
+
+            assert!(is_timeout(&e), "{:#}", e);
+            assert!(start.elapsed() < Duration::from_secs(1));
+            assert_eq!(1, mock_upstream.join().unwrap());
+        }
+    }
+}
+
+/// Requests the playlists and manifests from the upstream.
+///
+/// Failed requests are retried with a jittered exponential backoff. The requests to an upstream
+/// fail fast with `CircuitOpen` after a number of consecutive failures, until a single trial
+/// request succeeds again.
+pub struct Upstream {
+    http_client: Client,
+    read_timeout: Duration,
+    timeout: Duration,
+    blocking_timeout: Duration,
+    max_retries: u32,
+    retry_backoff: Duration,
+    failure_threshold: u32,
+    open_duration: Duration,
+    circuit_breakers: Mutex<HashMap<String, CircuitBreaker>>,
+}
+
+#[derive(Debug, ThisError)]
+#[error("the circuit breaker of the upstream is open")]
+pub struct CircuitOpen;
+
+impl Upstream {
+    /// The connect timeout has to be configured on the `http_client`.
+    pub fn new(http_client: Client, config: &config::Upstream) -> Upstream {
+        Upstream {
+            http_client,
+            read_timeout: config.read_timeout,
+            timeout: config.timeout,
+            blocking_timeout: config.blocking_timeout,
+            max_retries: config.max_retries,
+            retry_backoff: config.retry_backoff,
+            failure_threshold: config.circuit_breaker.failure_threshold,
+            open_duration: config.circuit_breaker.open_duration,
+            circuit_breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Requests the body of `url` and sets `status` to the status of the last response.
+    ///
+    /// `blocking` requests are Low-Latency HLS blocking playlist reloads, which are held by the
+    /// upstream until the requested segment is available. The retries have to finish within the
+    /// total timeout of the first request.
+    pub async fn get(
+        &self,
+        url: &Url,
+        blocking: bool,
+        span: &mut Span,
+        status: &mut Option<u16>,
+    ) -> anyhow::Result<String> {
+        let origin = url.origin().ascii_serialization();
+        let deadline = Instant::now()
+            + match blocking {
+                true => self.blocking_timeout,
+                false => self.timeout,
+            };
+        let mut attempt = 0;
+
+        loop {
+            if !self.allow_request(&origin) {
+                metrics::UPSTREAM_CIRCUIT_OPEN.inc();
+                return Err(CircuitOpen.into());
+            }
+
+            *status = None;
+            let result = self.request(url, blocking, deadline, span, status).await;
+
+            // missing playlists are no failure of the upstream
+            let failed = match (&result, *status) {
+                (Ok(_), _) => false,
+                (Err(_), Some(status)) => status >= 500,
+                (Err(_), None) => true,
+            };
+
+            self.record_result(&origin, failed);
+
+            let delay = retry_delay(self.retry_backoff, attempt);
+
+            // a blocking reload that timed out would only be held by the upstream again
+            let e = match result {
+                Err(e)
+                    if failed
+                        && attempt < self.max_retries
+                        && !(blocking && is_timeout(&e))
+                        && Instant::now() + delay < deadline =>
+                {
+                    e
+                }
+                result => {
+                    if attempt > 0 {
+                        span.set_attribute("http.retries", attempt);
+                    }
+
+                    return result;
+                }
+            };
+
+            warn!("Retrying request to upstream url `{}`: {:#}", url, e);
+            metrics::UPSTREAM_RETRIES.inc();
+
+            time::delay_for(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn request(
+        &self,
+        url: &Url,
+        blocking: bool,
+        deadline: Instant,
+        span: &mut Span,
+        status: &mut Option<u16>,
+    ) -> anyhow::Result<String> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let read_timeout = match blocking {
+            true => timeout,
+            false => self.read_timeout.min(timeout),
+        };
+
+        let response = self
+            .http_client
+            .get(url.clone())
+            .header("traceparent", span.context().traceparent().as_str())
+            .timeout(timeout)
+            .send()
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to retrieve playlist from upstream url `{}`",
+                    url.as_str()
+                )
+            })?;
+
+        *status = Some(response.status().as_u16());
+        span.set_attribute("http.status_code", response.status().as_u16());
+
+        let mut response = response.error_for_status().with_context(|| {
+            format!(
+                "Upstream playlist url `{}` responded with an error",
+                url.as_str()
+            )
+        })?;
+
+        let mut body = vec![];
+
+        while let Some(chunk) = time::timeout(read_timeout, response.chunk())
+            .await
+            .with_context(|| format!("Upstream playlist url `{}` stalled", url.as_str()))?
+            .with_context(|| {
+                format!(
+                    "Failed to retrieve body from upstream playlist response from url `{}`",
+                    url.as_str()
+                )
+            })?
+        {
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn allow_request(&self, origin: &str) -> bool {
+        let mut circuit_breakers = self.circuit_breakers.lock().unwrap();
+
+        circuit_breakers
+            .get_mut(origin)
+            .map(|circuit_breaker| circuit_breaker.allow(Instant::now()))
+            .unwrap_or(true)
+    }
+
+    fn record_result(&self, origin: &str, failed: bool) {
+        let mut circuit_breakers = self.circuit_breakers.lock().unwrap();
+        let (failure_threshold, open_duration) = (self.failure_threshold, self.open_duration);
+        let circuit_breaker = circuit_breakers
+            .entry(origin.to_string())
+            .or_insert_with(|| CircuitBreaker::new(failure_threshold, open_duration));
+
+        let was_open = circuit_breaker.open_until.is_some();
+
+        if circuit_breaker.record(failed, Instant::now()) {
+            warn!(
+                "Opened the circuit breaker of upstream `{}` after {} failed requests",
+                origin, circuit_breaker.failures
+            );
+        } else if was_open && !failed {
+            info!("Closed the circuit breaker of upstream `{}`", origin);
+        }
+    }
+}
+
+/// Whether the error is caused by a timeout of the upstream.
+pub fn is_timeout(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.is::<time::Elapsed>()
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .map(|e| e.is_timeout())
+                .unwrap_or(false)
+    })
+}
+
+/// A random delay up to the exponentially growing backoff, so that the retries of
+/// concurrent requests don't hit the upstream at once.
+fn retry_delay(backoff: Duration, attempt: u32) -> Duration {
+    let max_delay = backoff.checked_mul(1 << attempt.min(16)).unwrap_or(backoff);
+
+    max_delay.mul_f64(rand::random())
+}
+
+struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(failure_threshold: u32, open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold,
+            open_duration,
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// Lets a single trial request through whenever the breaker was open for its open duration.
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.open_until {
+            Some(open_until) if now < open_until => false,
+            Some(_) => {
+                self.open_until = Some(now + self.open_duration);
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Records the result of a request, returns whether the breaker opened.
+    fn record(&mut self, failed: bool, now: Instant) -> bool {
+        if !failed {
+            self.failures = 0;
+            self.open_until = None;
+            return false;
+        }
+
+        self.failures = self.failures.saturating_add(1);
+
+        if self.failures < self.failure_threshold {
+            return false;
+        }
+
+        let was_open = self.open_until.is_some();
+        self.open_until = Some(now + self.open_duration);
+
+        !was_open
+    }
+}
@@ -1,7 +1,12 @@
+// the tests of a module are kept in front of its code
+#![allow(clippy::items_after_test_module)]
+
 pub mod config;
 pub mod edge_node_discovery;
 pub mod http;
+pub mod metrics;
 pub mod playlist;
+pub mod trace;
 
 #[cfg(test)]
 pub mod test_util;
@@ -1,17 +1,27 @@
 use balancing_playlist_spreader::{
-    edge_node_discovery::ConsulEdgeNodeProvider,
+    edge_node_discovery::{
+        CompositeEdgeNodeProvider, ConsulEdgeNodeProvider, DnsSrvEdgeNodeProvider,
+        EdgeNodeProvider, FileEdgeNodeProvider, HealthCheckedEdgeNodeProvider,
+        KubernetesEdgeNodeProvider, NodeGroupFallback,
+    },
     http::create_routes,
     playlist::{
-        CombinedPlaylistRewriter, HmacUrlSigner, PlaylistRewriter, SegmentLoadDistributor,
-        SegmentUrlSigner,
+        CombinedPlaylistRewriter, DashManifestRewriter, DistributionStrategy, HmacUrlSigner,
+        KeyUrlRewriter, LoadAwareDistribution, PlaylistRewriter, RandomDistribution, ReportedLoad,
+        SegmentAffinityDistribution, SegmentLoadDistributor, SegmentUrlSigner,
+        SessionAffinityDistribution,
     },
+    trace::{self, Tracer},
 };
 
-use balancing_playlist_spreader::config::load_config;
+use anyhow::Context;
+use balancing_playlist_spreader::config::{load_config, Config, Discovery, Distribution};
+use log::{info, warn};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use reqwest::Client;
 use std::sync::Arc;
+use tokio::time;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -19,32 +29,131 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = load_config()?;
 
-    let consul = consul_api_client::Client::new(
-        consul_api_client::Config::builder()
-            .address(config.consul.base_url.to_string())
-            .build()?,
-    )?;
+    if let Some(tracing) = &config.tracing {
+        let exporter_client = Client::builder().timeout(tracing.export_timeout).build()?;
+
+        trace::set_tracer(Tracer::from_config(exporter_client, tracing));
+    }
+
+    let mut edge_node_provider = create_edge_node_provider(&config.discovery, &config)?;
+
+    if let Some(health_check) = &config.health_check {
+        edge_node_provider = Arc::new(HealthCheckedEdgeNodeProvider::new(
+            Client::new(),
+            edge_node_provider,
+            health_check,
+        ));
+    }
+
+    let updating_edge_nodes_provider = Arc::new(NodeGroupFallback::from_config(
+        edge_node_provider,
+        &config.node_groups,
+    ));
 
-    let updating_edge_nodes_provider =
-        ConsulEdgeNodeProvider::new(consul, config.consul.update_interval);
+    // playlists rewritten without edge nodes would send the viewers to the origin
+    let discovered = time::timeout(
+        config.discovery_timeout,
+        updating_edge_nodes_provider.readiness().wait(),
+    )
+    .await
+    .unwrap_or(false);
+
+    if discovered {
+        info!("Discovered the edge nodes");
+    } else {
+        warn!(
+            "Edge nodes weren't discovered within {:?}, starting unready",
+            config.discovery_timeout
+        );
+    }
 
     let segment_signer = SegmentUrlSigner::new(
         HmacUrlSigner::new(config.playlist.segment_signing.key.clone()),
         config.playlist.segment_signing.duration,
     );
 
-    let segment_load_distributor =
-        SegmentLoadDistributor::new(updating_edge_nodes_provider, Box::new(StdRng::from_entropy));
+    let distribution_strategy: Arc<dyn DistributionStrategy> = match &config.playlist.distribution {
+        Distribution::Random => Arc::new(RandomDistribution::new(Box::new(StdRng::from_entropy))),
+        Distribution::SessionAffinity { virtual_nodes } => {
+            Arc::new(SessionAffinityDistribution::new(*virtual_nodes))
+        }
+        Distribution::SegmentAffinity => Arc::new(SegmentAffinityDistribution),
+        Distribution::LoadAware { max_load } => Arc::new(LoadAwareDistribution::new(
+            Box::new(StdRng::from_entropy),
+            ReportedLoad,
+            *max_load,
+        )),
+    };
+
+    let segment_load_distributor = SegmentLoadDistributor::new(
+        Arc::clone(&updating_edge_nodes_provider),
+        Arc::clone(&distribution_strategy),
+    );
+
+    let key_url_rewriter = KeyUrlRewriter::from_config(&config.playlist.key_delivery);
+
+    let rewriters: Vec<Box<dyn PlaylistRewriter>> = vec![
+        Box::new(segment_load_distributor),
+        Box::new(key_url_rewriter),
+        Box::new(segment_signer),
+    ];
 
-    let rewriters: Vec<Box<dyn PlaylistRewriter>> =
-        vec![Box::new(segment_load_distributor), Box::new(segment_signer)];
+    let dash_signing_duration = match &config.playlist.dash {
+        Some(dash) => dash.signing_duration,
+        None => config.playlist.segment_signing.duration,
+    };
+
+    let dash_manifest_rewriter = DashManifestRewriter::new(
+        Arc::clone(&updating_edge_nodes_provider),
+        distribution_strategy,
+        HmacUrlSigner::new(config.playlist.segment_signing.key.clone()),
+        dash_signing_duration,
+    );
+
+    let upstream_client = Client::builder()
+        .connect_timeout(config.playlist.upstream.connect_timeout)
+        .build()?;
 
     let routes = create_routes(
-        Client::new(),
+        upstream_client,
         Arc::clone(&config),
         Arc::new(CombinedPlaylistRewriter::new(rewriters)),
+        Arc::new(dash_manifest_rewriter),
+        updating_edge_nodes_provider,
     );
 
     warp::serve(routes).run(config.http.socket).await;
     Ok(())
 }
+
+fn create_edge_node_provider(
+    discovery: &Discovery,
+    config: &Config,
+) -> anyhow::Result<Arc<dyn EdgeNodeProvider>> {
+    let edge_node_provider: Arc<dyn EdgeNodeProvider> = match discovery {
+        Discovery::Consul { consul } => {
+            let consul = consul
+                .as_ref()
+                .or(config.consul.as_ref())
+                .context("Missing consul config for the consul edge node discovery")?;
+
+            Arc::new(ConsulEdgeNodeProvider::new(Client::new(), consul)?)
+        }
+        Discovery::File(file) => Arc::new(FileEdgeNodeProvider::new(file)?),
+        Discovery::DnsSrv(dns_srv) => Arc::new(DnsSrvEdgeNodeProvider::new(dns_srv)?),
+        Discovery::Kubernetes(kubernetes) => Arc::new(KubernetesEdgeNodeProvider::new(kubernetes)?),
+        Discovery::Composite { sources } => {
+            let sources = sources
+                .iter()
+                .map(|source| {
+                    create_edge_node_provider(&source.discovery, config)
+                        .map(|provider| (source.priority, provider))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            Arc::new(CompositeEdgeNodeProvider::new(sources))
+        }
+    };
+
+    Ok(edge_node_provider)
+}
@@ -0,0 +1,167 @@
+use lazy_static::lazy_static;
+use log::error;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    Encoder, GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec, Opts, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        CONSUL_REFRESHES.with_label_values(&["success"]).inc();
+
+        let ages =
+            AgeGaugeVec::register("grosp_test_age_seconds", "Test age", &["source"]).unwrap();
+        ages.set_age_fn(&["known"], || Some(Duration::from_millis(1500)));
+        ages.set_age_fn(&["unknown"], || None);
+
+        let metrics = encode();
+
+        assert!(metrics.contains("grosp_consul_refreshes_total{result=\"success\"}"));
+        assert!(metrics.contains("grosp_test_age_seconds{source=\"known\"} 1.5\n"));
+        assert!(!metrics.contains("grosp_test_age_seconds{source=\"unknown\"}"));
+    }
+}
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "grosp_http_requests_total",
+        "Handled http requests by route and status",
+        &["route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "grosp_http_request_duration_seconds",
+        "Duration of the handled http requests by route and status",
+        &["route", "status"]
+    )
+    .unwrap();
+    pub static ref UPSTREAM_REQUEST_DURATION: Histogram = register_histogram!(
+        "grosp_upstream_request_duration_seconds",
+        "Duration of the playlist and manifest requests to the upstream"
+    )
+    .unwrap();
+    pub static ref UPSTREAM_ERRORS: IntCounter = register_int_counter!(
+        "grosp_upstream_errors_total",
+        "Failed playlist and manifest requests to the upstream"
+    )
+    .unwrap();
+    pub static ref UPSTREAM_RETRIES: IntCounter = register_int_counter!(
+        "grosp_upstream_retries_total",
+        "Retried playlist and manifest requests to the upstream"
+    )
+    .unwrap();
+    pub static ref UPSTREAM_CIRCUIT_OPEN: IntCounter = register_int_counter!(
+        "grosp_upstream_circuit_open_total",
+        "Requests to the upstream that failed fast because of an open circuit breaker"
+    )
+    .unwrap();
+    pub static ref REWRITE_DURATION: HistogramVec = register_histogram_vec!(
+        "grosp_rewrite_duration_seconds",
+        "Duration of the rewrites by kind of playlist",
+        &["kind"],
+        vec![0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1]
+    )
+    .unwrap();
+    pub static ref ASSIGNED_SEGMENTS: IntCounterVec = register_int_counter_vec!(
+        "grosp_assigned_segments_total",
+        "Segments assigned to an edge node by node group of the viewer and edge node",
+        &["node_group", "edge_node"]
+    )
+    .unwrap();
+    pub static ref JWT_REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        "grosp_jwt_rejections_total",
+        "Requests rejected by the jwt validation by reason",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref CONSUL_REFRESHES: IntCounterVec = register_int_counter_vec!(
+        "grosp_consul_refreshes_total",
+        "Consul queries for the edge nodes by result",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref CONSUL_EDGE_NODES_AGE: AgeGaugeVec = AgeGaugeVec::register(
+        "grosp_consul_edge_nodes_age_seconds",
+        "Time since the edge nodes were last confirmed by consul by service and datacenter",
+        &["service", "datacenter"]
+    )
+    .unwrap();
+}
+
+type AgeFn = Box<dyn Fn() -> Option<Duration> + Send + Sync>;
+
+/// Gauges with the ages returned by the functions of their label values at the time of the
+/// scrape, a gauge is left out while its age is unknown.
+#[derive(Clone)]
+pub struct AgeGaugeVec {
+    gauges: GaugeVec,
+    ages: Arc<Mutex<HashMap<Vec<String>, AgeFn>>>,
+}
+
+impl AgeGaugeVec {
+    pub fn register(name: &str, help: &str, label_names: &[&str]) -> prometheus::Result<Self> {
+        let age_gauges = AgeGaugeVec {
+            gauges: GaugeVec::new(Opts::new(name, help), label_names)?,
+            ages: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        prometheus::register(Box::new(age_gauges.clone()))?;
+
+        Ok(age_gauges)
+    }
+
+    /// Sets the function of the gauge with the label values, replacing a previous one.
+    pub fn set_age_fn<F>(&self, label_values: &[&str], age: F)
+    where
+        F: Fn() -> Option<Duration> + Send + Sync + 'static,
+    {
+        let label_values = label_values.iter().map(|value| value.to_string()).collect();
+
+        self.ages
+            .lock()
+            .unwrap()
+            .insert(label_values, Box::new(age));
+    }
+}
+
+impl Collector for AgeGaugeVec {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauges.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.gauges.reset();
+
+        for (label_values, age) in self.ages.lock().unwrap().iter() {
+            if let Some(age) = age() {
+                let label_values: Vec<&str> = label_values.iter().map(String::as_str).collect();
+
+                self.gauges
+                    .with_label_values(&label_values)
+                    .set(age.as_secs_f64());
+            }
+        }
+
+        self.gauges.collect()
+    }
+}
+
+/// The registered metrics in the Prometheus text format.
+pub fn encode() -> String {
+    let mut buffer = vec![];
+
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {:?}", e);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -1,18 +1,102 @@
+mod dash_manifest_rewriter;
+mod distribution;
+mod key_url_rewriter;
+mod low_latency;
+mod master_playlist_rewriter;
 mod segment_load_distributor;
 mod segment_url_signer;
 
+use crate::trace::{Span, SpanKind};
 use hls_m3u8::MediaPlaylist;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use url::Url;
 
+pub use dash_manifest_rewriter::DashManifestRewriter;
+pub use dash_manifest_rewriter::ManifestRewriter;
+pub use distribution::DistributionStrategy;
+pub use distribution::EdgeNodeSelector;
+pub use distribution::LoadAwareDistribution;
+pub use distribution::LoadSource;
+pub use distribution::RandomDistribution;
+pub use distribution::ReportedLoad;
+pub use distribution::RngProvider;
+pub use distribution::SegmentAffinityDistribution;
+pub use distribution::SessionAffinityDistribution;
+pub use key_url_rewriter::KeyUrlRewriter;
+pub use key_url_rewriter::KeyUrlRule;
+pub use low_latency::LowLatencyTag;
+pub use low_latency::LowLatencyTagKind;
+pub use low_latency::LowLatencyTags;
+pub use master_playlist_rewriter::MasterPlaylistRewriter;
 pub use segment_load_distributor::SegmentLoadDistributor;
 pub use segment_url_signer::HmacUrlSigner;
 pub use segment_url_signer::SegmentUrlSigner;
+pub use segment_url_signer::UrlSigner;
+
+/// Information about the request a playlist is rewritten for.
+#[derive(Debug, Clone, Copy)]
+pub struct RewriteContext<'a> {
+    pub node_group: &'a str,
+    /// Identifies the viewer session, requests of the same viewer share the session key.
+    pub session_key: &'a str,
+    /// Upstream url of the rewritten playlist, the relative uris of the playlist are based on it
+    pub playlist_url: &'a Url,
+    /// Span of the request, the rewrites are traced as its children
+    pub span: Option<&'a Span>,
+    /// Collects the edge nodes chosen by the rewriters
+    pub assignments: Option<&'a Assignments>,
+}
+
+/// Edge nodes chosen while rewriting a playlist
+#[derive(Debug, Default)]
+pub struct Assignments {
+    segments: AtomicUsize,
+    edge_nodes: Mutex<Vec<Url>>,
+}
+
+impl Assignments {
+    pub fn assign_segment(&self, edge_node: &Url) {
+        self.segments.fetch_add(1, Ordering::Relaxed);
+        self.add_edge_node(edge_node);
+    }
+
+    pub fn add_edge_node(&self, edge_node: &Url) {
+        let mut edge_nodes = self.edge_nodes.lock().unwrap();
+
+        if !edge_nodes.contains(edge_node) {
+            edge_nodes.push(edge_node.clone());
+        }
+    }
+
+    /// Number of segments assigned to an edge node
+    pub fn segments(&self) -> usize {
+        self.segments.load(Ordering::Relaxed)
+    }
+
+    pub fn edge_nodes(&self) -> Vec<Url> {
+        self.edge_nodes.lock().unwrap().clone()
+    }
+}
 
 pub trait PlaylistRewriter: Send + Sync {
+    /// Identifies the rewriter in traces
+    fn name(&self) -> &'static str;
+
     fn rewrite_playlist<'a>(
         &self,
         playlist: MediaPlaylist<'a>,
-        node_group: &str,
+        context: &RewriteContext,
     ) -> MediaPlaylist<'a>;
+
+    /// Rewrites the Low-Latency HLS tags of the already rewritten `playlist`.
+    fn rewrite_low_latency_tags(
+        &self,
+        _playlist: &MediaPlaylist,
+        _tags: &mut LowLatencyTags,
+        _context: &RewriteContext,
+    ) {
+    }
 }
 
 pub struct CombinedPlaylistRewriter {
@@ -26,19 +110,47 @@ impl CombinedPlaylistRewriter {
 }
 
 impl PlaylistRewriter for CombinedPlaylistRewriter {
+    fn name(&self) -> &'static str {
+        "combined"
+    }
+
     fn rewrite_playlist<'a>(
         &self,
         playlist: MediaPlaylist<'a>,
-        node_group: &str,
+        context: &RewriteContext,
     ) -> MediaPlaylist<'a> {
         let mut playlist = playlist;
 
         for rewriter in self.rewriters.iter() {
-            playlist = rewriter.rewrite_playlist(playlist, node_group);
+            let _span = rewriter_span("rewrite_playlist", rewriter.as_ref(), context);
+            playlist = rewriter.rewrite_playlist(playlist, context);
         }
 
         playlist
     }
+
+    fn rewrite_low_latency_tags(
+        &self,
+        playlist: &MediaPlaylist,
+        tags: &mut LowLatencyTags,
+        context: &RewriteContext,
+    ) {
+        for rewriter in self.rewriters.iter() {
+            let _span = rewriter_span("rewrite_low_latency_tags", rewriter.as_ref(), context);
+            rewriter.rewrite_low_latency_tags(playlist, tags, context);
+        }
+    }
+}
+
+fn rewriter_span(
+    name: &'static str,
+    rewriter: &dyn PlaylistRewriter,
+    context: &RewriteContext,
+) -> Option<Span> {
+    let mut span = context.span?.child(name, SpanKind::Internal);
+    span.set_attribute("rewriter", rewriter.name());
+
+    Some(span)
 }
 
 #[cfg(test)]
@@ -47,7 +159,7 @@ mod tests {
     use std::time::Duration;
 
     #[allow(dead_code)]
-    pub fn build_segment(uri: &'static str) -> MediaSegment {
+    pub fn build_segment(uri: &'static str) -> MediaSegment<'static> {
         MediaSegment::builder()
             .duration(Duration::from_secs(3))
             .uri(uri)
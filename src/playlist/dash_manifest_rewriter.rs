@@ -0,0 +1,347 @@
+use crate::edge_node_discovery::EdgeNodeProvider;
+use crate::playlist::segment_load_distributor::try_to_change_uri_host;
+use crate::playlist::{DistributionStrategy, RewriteContext, UrlSigner};
+use anyhow::Context;
+use log::warn;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edge_node_discovery::{EdgeNode, EdgeNodeList};
+    use crate::playlist::RandomDistribution;
+    use crate::test_util::rewrite_context;
+    use rand::rngs::mock::StepRng;
+
+    struct MockEdgeNodeProvider;
+
+    impl EdgeNodeProvider for MockEdgeNodeProvider {
+        fn get_edge_nodes(&self, node_group: &str) -> EdgeNodeList {
+            Arc::new(vec![EdgeNode {
+                url: Url::parse("https://alpha.com:2323").unwrap(),
+                group: String::from(node_group),
+                weight: 1,
+                load: None,
+            }])
+        }
+    }
+
+    struct MockUrlSigner;
+    impl UrlSigner for MockUrlSigner {
+        fn sign(&self, mut url: Url, _expiry_timestamp: u64) -> Url {
+            let path = url.path().to_string();
+            url.query_pairs_mut().append_pair("signed", &path);
+
+            url
+        }
+    }
+
+    fn rewriter() -> DashManifestRewriter<MockEdgeNodeProvider, MockUrlSigner> {
+        DashManifestRewriter::new(
+            MockEdgeNodeProvider,
+            Arc::new(RandomDistribution::new(Box::new(|| StepRng::new(0, 1)))),
+            MockUrlSigner,
+            Duration::from_secs(3),
+        )
+    }
+
+    #[test]
+    fn test_segment_template_rewriting() {
+        let manifest = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static">
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate timescale="1000" media="video/$RepresentationID$/$Number%05d$.m4s" initialization="video/$RepresentationID$/init.mp4"/>
+      <Representation id="720p" bandwidth="3000000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+        let manifest = rewriter()
+            .rewrite_manifest(
+                manifest,
+                &Url::parse("https://upstream.com/live/stream.mpd").unwrap(),
+                &rewrite_context(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static">
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate timescale="1000" media="https://alpha.com:2323/live/video/$RepresentationID$/$Number%05d$.m4s?p=%2Flive%2Fvideo%2F&amp;signed=%2Flive%2Fvideo%2F" initialization="https://alpha.com:2323/live/video/$RepresentationID$/init.mp4?p=%2Flive%2Fvideo%2F&amp;signed=%2Flive%2Fvideo%2F"/>
+      <Representation id="720p" bandwidth="3000000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+            manifest
+        );
+    }
+
+    #[test]
+    fn test_base_url_rewriting() {
+        let manifest = r#"<MPD>
+  <BaseURL>https://origin.internal/vod/</BaseURL>
+  <Period>
+    <AdaptationSet>
+      <Representation id="audio">
+        <BaseURL>audio.mp4</BaseURL>
+        <SegmentBase indexRange="0-100"/>
+      </Representation>
+      <Representation id="video">
+        <SegmentTemplate media="video/$Number$.m4s"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+        let manifest = rewriter()
+            .rewrite_manifest(
+                manifest,
+                &Url::parse("https://upstream.com/live/stream.mpd").unwrap(),
+                &rewrite_context(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            r#"<MPD>
+  <BaseURL>https://alpha.com:2323/vod/</BaseURL>
+  <Period>
+    <AdaptationSet>
+      <Representation id="audio">
+        <BaseURL>https://alpha.com:2323/vod/audio.mp4?signed=%2Fvod%2Faudio.mp4</BaseURL>
+        <SegmentBase indexRange="0-100"/>
+      </Representation>
+      <Representation id="video">
+        <SegmentTemplate media="https://alpha.com:2323/vod/video/$Number$.m4s?p=%2Fvod%2Fvideo%2F&amp;signed=%2Fvod%2Fvideo%2F"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+            manifest
+        );
+    }
+}
+
+pub trait ManifestRewriter: Send + Sync {
+    fn rewrite_manifest(
+        &self,
+        manifest: &str,
+        manifest_url: &Url,
+        context: &RewriteContext,
+    ) -> anyhow::Result<String>;
+}
+
+/// Rewrites the `BaseURL` elements and the `SegmentTemplate` urls of a MPEG-DASH manifest,
+/// so that the segments are requested from a single edge node chosen by the distribution strategy.
+///
+/// Templated segment urls are signed with the path prefix in front of the first template
+/// identifier, which is passed along in the `p` query parameter.
+pub struct DashManifestRewriter<T, S>
+where
+    T: EdgeNodeProvider,
+    S: UrlSigner,
+{
+    edge_node_provider: T,
+    strategy: Arc<dyn DistributionStrategy>,
+    signer: S,
+    expiry_duration: Duration,
+}
+
+impl<T, S> DashManifestRewriter<T, S>
+where
+    T: EdgeNodeProvider,
+    S: UrlSigner,
+{
+    pub fn new(
+        edge_node_provider: T,
+        strategy: Arc<dyn DistributionStrategy>,
+        signer: S,
+        expiry_duration: Duration,
+    ) -> DashManifestRewriter<T, S> {
+        DashManifestRewriter {
+            edge_node_provider,
+            strategy,
+            signer,
+            expiry_duration,
+        }
+    }
+
+    fn rewrite_segment_template(
+        &self,
+        element: BytesStart,
+        base_url: &Url,
+        edge_node: &Url,
+        valid_until: u64,
+    ) -> anyhow::Result<BytesStart<'static>> {
+        if element.local_name() != b"SegmentTemplate" {
+            return Ok(element.into_owned());
+        }
+
+        let mut rewritten = BytesStart::owned_name(element.name().to_vec());
+
+        for attribute in element.attributes() {
+            let attribute = attribute?;
+
+            match attribute.key {
+                b"media" | b"initialization" => {
+                    let template = attribute.unescaped_value()?;
+                    let template = str::from_utf8(&template)?;
+                    let url = base_url.join(template)?;
+                    let url = try_to_change_uri_host(url.as_str(), edge_node)
+                        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+                    rewritten.push_attribute((
+                        str::from_utf8(attribute.key)?,
+                        self.sign(url, valid_until).as_str(),
+                    ));
+                }
+                _ => rewritten.push_attribute(attribute),
+            }
+        }
+
+        Ok(rewritten)
+    }
+
+    fn rewrite_base_url(
+        &self,
+        text: BytesText,
+        base_url: &Url,
+        edge_node: &Url,
+        valid_until: u64,
+    ) -> anyhow::Result<(Url, BytesText<'static>)> {
+        let value = text.unescaped()?;
+        let value = str::from_utf8(&value)?.trim();
+        let resolved = base_url.join(value)?;
+        let url = try_to_change_uri_host(resolved.as_str(), edge_node)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let url = self.sign(url, valid_until);
+        let text = BytesText::from_plain_str(url.as_str()).into_owned();
+
+        Ok((resolved, text))
+    }
+
+    fn sign(&self, url: Url, valid_until: u64) -> Url {
+        let path = url.path();
+
+        // directories can't be signed, the urls of their content are signed instead
+        if path.ends_with('/') {
+            return url;
+        }
+
+        match path.find('$') {
+            Some(template_start) => {
+                let prefix_end = path[..template_start].rfind('/').map_or(0, |i| i + 1);
+                let prefix = path[..prefix_end].to_string();
+
+                let mut prefix_url = url.clone();
+                prefix_url.set_path(&prefix);
+                prefix_url.set_query(None);
+                let prefix_url = self.signer.sign(prefix_url, valid_until);
+
+                let mut url = url;
+                url.query_pairs_mut()
+                    .append_pair("p", &prefix)
+                    .extend_pairs(prefix_url.query_pairs());
+
+                url
+            }
+            None => self.signer.sign(url, valid_until),
+        }
+    }
+}
+
+impl<T, S> ManifestRewriter for DashManifestRewriter<T, S>
+where
+    T: EdgeNodeProvider,
+    S: UrlSigner,
+{
+    fn rewrite_manifest(
+        &self,
+        manifest: &str,
+        manifest_url: &Url,
+        context: &RewriteContext,
+    ) -> anyhow::Result<String> {
+        let edge_nodes = self.edge_node_provider.get_edge_nodes(context.node_group);
+        let edge_node = match self
+            .strategy
+            .selector(&edge_nodes, context)
+            .select(manifest_url.as_str())
+        {
+            Some(edge_node) => edge_node,
+            None => {
+                warn!(
+                    "No edge nodes available for node group `{}`",
+                    context.node_group
+                );
+                return Ok(manifest.to_string());
+            }
+        };
+
+        if let Some(assignments) = context.assignments {
+            assignments.add_edge_node(edge_node);
+        }
+
+        let valid_until = (SystemTime::now() + self.expiry_duration)
+            .duration_since(UNIX_EPOCH)
+            .context("Failed to get a valid expiry unix timestamp")?
+            .as_secs();
+
+        let mut reader = Reader::from_str(manifest);
+        let mut writer = Writer::new(Vec::with_capacity(manifest.len()));
+        let mut buf = Vec::new();
+
+        // resolved base urls together with the depth of the element they belong to
+        let mut base_urls: Vec<(usize, Url)> = vec![(0, manifest_url.clone())];
+        let mut depth = 0;
+        let mut in_base_url = false;
+
+        loop {
+            let base_url = &base_urls.last().unwrap().1;
+
+            match reader.read_event(&mut buf)? {
+                Event::Start(element) => {
+                    depth += 1;
+                    in_base_url = element.local_name() == b"BaseURL";
+
+                    let element =
+                        self.rewrite_segment_template(element, base_url, edge_node, valid_until)?;
+                    writer.write_event(Event::Start(element))?;
+                }
+                Event::Empty(element) => {
+                    let element =
+                        self.rewrite_segment_template(element, base_url, edge_node, valid_until)?;
+                    writer.write_event(Event::Empty(element))?;
+                }
+                Event::Text(text) if in_base_url => {
+                    let (resolved, text) =
+                        self.rewrite_base_url(text, base_url, edge_node, valid_until)?;
+                    writer.write_event(Event::Text(text))?;
+
+                    // the base url applies to the parent element of the BaseURL element
+                    base_urls.push((depth - 1, resolved));
+                }
+                Event::End(element) => {
+                    in_base_url = false;
+                    base_urls.retain(|(base_url_depth, _)| *base_url_depth != depth);
+                    depth -= 1;
+
+                    writer.write_event(Event::End(element))?;
+                }
+                Event::Eof => break,
+                event => writer.write_event(event)?,
+            }
+
+            buf.clear();
+        }
+
+        String::from_utf8(writer.into_inner()).context("Rewritten manifest is not valid utf-8")
+    }
+}
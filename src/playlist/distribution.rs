@@ -0,0 +1,39 @@
+mod load_aware;
+mod random;
+mod segment_affinity;
+mod session_affinity;
+
+use crate::edge_node_discovery::EdgeNodeList;
+use crate::playlist::RewriteContext;
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use url::Url;
+
+pub use load_aware::LoadAwareDistribution;
+pub use load_aware::LoadSource;
+pub use load_aware::ReportedLoad;
+pub use random::RandomDistribution;
+pub use random::RngProvider;
+pub use segment_affinity::SegmentAffinityDistribution;
+pub use session_affinity::SessionAffinityDistribution;
+
+/// Decides which edge nodes the uris of a playlist are distributed to.
+pub trait DistributionStrategy: Send + Sync {
+    /// Creates a selector for the uris of a single playlist request.
+    fn selector<'a>(
+        &'a self,
+        edge_nodes: &'a EdgeNodeList,
+        context: &RewriteContext,
+    ) -> Box<dyn EdgeNodeSelector<'a> + 'a>;
+}
+
+pub trait EdgeNodeSelector<'a> {
+    fn select(&mut self, uri: &str) -> Option<&'a Url>;
+}
+
+/// Stable hash that yields the same value on every instance of the spreader.
+fn hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
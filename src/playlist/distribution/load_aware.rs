@@ -0,0 +1,165 @@
+use super::random::RndEdgeNodeUrlIter;
+use super::{DistributionStrategy, EdgeNodeSelector, RngProvider};
+use crate::edge_node_discovery::{EdgeNode, EdgeNodeList};
+use crate::playlist::RewriteContext;
+use log::warn;
+use rand::Rng;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::rewrite_context;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+    use url::Url;
+
+    fn edge_node(url: &str, load: Option<f64>) -> EdgeNode {
+        EdgeNode {
+            url: Url::parse(url).unwrap(),
+            group: String::from("test"),
+            weight: 1,
+            load,
+        }
+    }
+
+    fn count_selections(
+        distribution: &dyn DistributionStrategy,
+        edge_nodes: &EdgeNodeList,
+    ) -> Vec<usize> {
+        let mut selector = distribution.selector(edge_nodes, &rewrite_context());
+        let mut counts = vec![0; edge_nodes.len()];
+
+        for _ in 0..10_000 {
+            let url = selector.select("/23.ts").unwrap();
+            let index = edge_nodes.iter().position(|en| &en.url == url).unwrap();
+            counts[index] += 1;
+        }
+
+        counts
+    }
+
+    #[test]
+    fn test_load_aware_distribution() {
+        let edge_nodes = Arc::new(vec![
+            edge_node("https://alpha.com", Some(0.25)),
+            edge_node("https://beta.com", Some(0.75)),
+            edge_node("https://gamma.com", Some(0.95)),
+        ]);
+
+        let distribution =
+            LoadAwareDistribution::new(Box::new(|| StdRng::seed_from_u64(23)), ReportedLoad, 0.9);
+
+        let counts = count_selections(&distribution, &edge_nodes);
+
+        // alpha has three times the spare capacity of beta, gamma is overloaded
+        assert!(counts[0] > 7_200 && counts[0] < 7_800, "{:?}", counts);
+        assert_eq!(0, counts[2]);
+    }
+
+    #[test]
+    fn test_all_edge_nodes_overloaded() {
+        let edge_nodes = Arc::new(vec![
+            edge_node("https://alpha.com", Some(0.95)),
+            edge_node("https://beta.com", None),
+        ]);
+
+        let distribution =
+            LoadAwareDistribution::new(Box::new(|| StdRng::seed_from_u64(23)), ReportedLoad, 0.0);
+
+        let counts = count_selections(&distribution, &edge_nodes);
+
+        assert!(counts.iter().all(|count| *count > 0), "{:?}", counts);
+    }
+}
+
+/// Provides the current utilisation of edge nodes.
+pub trait LoadSource: Send + Sync {
+    /// Utilisation of the edge node between 0 and 1, `None` if it isn't known.
+    fn utilisation(&self, edge_node: &EdgeNode) -> Option<f64>;
+}
+
+/// The load the edge nodes report in the `load` key of their service meta.
+pub struct ReportedLoad;
+
+impl LoadSource for ReportedLoad {
+    fn utilisation(&self, edge_node: &EdgeNode) -> Option<f64> {
+        edge_node.load
+    }
+}
+
+/// Chooses a random edge node for every uri, in proportion to the spare capacity of the
+/// edge nodes. Edge nodes with an utilisation of at least `max_load` are skipped, unless
+/// all edge nodes of the group are that busy.
+pub struct LoadAwareDistribution<U, L>
+where
+    U: Rng + Send + Sync,
+    L: LoadSource,
+{
+    rng_provider: RngProvider<U>,
+    load_source: L,
+    max_load: f64,
+}
+
+impl<U, L> LoadAwareDistribution<U, L>
+where
+    U: Rng + Send + Sync,
+    L: LoadSource,
+{
+    pub fn new(
+        rng_provider: RngProvider<U>,
+        load_source: L,
+        max_load: f64,
+    ) -> LoadAwareDistribution<U, L> {
+        LoadAwareDistribution {
+            rng_provider,
+            load_source,
+            max_load,
+        }
+    }
+
+    fn spare_capacity(&self, edge_node: &EdgeNode) -> f64 {
+        // edge nodes without a known load are considered idle
+        let load = self
+            .load_source
+            .utilisation(edge_node)
+            .unwrap_or(0.0)
+            .clamp(0.0, 1.0);
+
+        if load >= self.max_load {
+            return 0.0;
+        }
+
+        f64::from(edge_node.weight) * (1.0 - load)
+    }
+}
+
+impl<U, L> DistributionStrategy for LoadAwareDistribution<U, L>
+where
+    U: Rng + Send + Sync + 'static,
+    L: LoadSource,
+{
+    fn selector<'a>(
+        &'a self,
+        edge_nodes: &'a EdgeNodeList,
+        context: &RewriteContext,
+    ) -> Box<dyn EdgeNodeSelector<'a> + 'a> {
+        let rng = (self.rng_provider)();
+        let weights: Vec<f64> = edge_nodes
+            .iter()
+            .map(|en| self.spare_capacity(en))
+            .collect();
+
+        let overloaded = weights.iter().all(|weight| *weight <= 0.0);
+        if overloaded && edge_nodes.iter().any(|en| en.weight > 0) {
+            warn!(
+                "All edge nodes of node group `{}` are overloaded, ignoring their load",
+                context.node_group
+            );
+
+            return Box::new(RndEdgeNodeUrlIter::new(edge_nodes, rng));
+        }
+
+        Box::new(RndEdgeNodeUrlIter::with_weights(edge_nodes, weights, rng))
+    }
+}
@@ -0,0 +1,152 @@
+use super::{DistributionStrategy, EdgeNodeSelector};
+use crate::edge_node_discovery::EdgeNodeList;
+use crate::playlist::RewriteContext;
+use log::warn;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edge_node_discovery::EdgeNode;
+    use crate::test_util::rewrite_context;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn edge_node(url: &str, weight: u32) -> EdgeNode {
+        EdgeNode {
+            url: Url::parse(url).unwrap(),
+            group: String::from("test"),
+            weight,
+            load: None,
+        }
+    }
+
+    #[test]
+    fn test_weighted_distribution() {
+        let edge_nodes = Arc::new(vec![
+            edge_node("https://alpha.com", 3),
+            edge_node("https://beta.com", 1),
+            edge_node("https://gamma.com", 0),
+        ]);
+
+        let distribution = RandomDistribution::new(Box::new(|| StdRng::seed_from_u64(23)));
+        let mut selector = distribution.selector(&edge_nodes, &rewrite_context());
+
+        let mut alpha = 0;
+        for _ in 0..10_000 {
+            match selector.select("/23.ts").unwrap().host_str() {
+                Some("alpha.com") => alpha += 1,
+                Some("beta.com") => {}
+                host => panic!("unexpected edge node {:?}", host),
+            }
+        }
+
+        assert!(alpha > 7_200 && alpha < 7_800, "alpha got {} uris", alpha);
+    }
+}
+
+pub type RngProvider<U> = Box<dyn Fn() -> U + Send + Sync>;
+
+/// Chooses a random edge node for every uri, in proportion to the weights of the edge nodes.
+pub struct RandomDistribution<U>
+where
+    U: Rng + Send + Sync,
+{
+    rng_provider: RngProvider<U>,
+}
+
+impl<U> RandomDistribution<U>
+where
+    U: Rng + Send + Sync,
+{
+    pub fn new(rng_provider: RngProvider<U>) -> RandomDistribution<U> {
+        RandomDistribution { rng_provider }
+    }
+}
+
+impl<U> DistributionStrategy for RandomDistribution<U>
+where
+    U: Rng + Send + Sync + 'static,
+{
+    fn selector<'a>(
+        &'a self,
+        edge_nodes: &'a EdgeNodeList,
+        _context: &RewriteContext,
+    ) -> Box<dyn EdgeNodeSelector<'a> + 'a> {
+        Box::new(RndEdgeNodeUrlIter::new(edge_nodes, (self.rng_provider)()))
+    }
+}
+
+enum Sampling {
+    Uniform,
+    Weighted(WeightedIndex<f64>),
+    Empty,
+}
+
+pub(super) struct RndEdgeNodeUrlIter<'a, T: Rng> {
+    edge_nodes: &'a EdgeNodeList,
+    sampling: Sampling,
+    rng: T,
+}
+
+impl<'a, T: Rng> RndEdgeNodeUrlIter<'a, T> {
+    pub(super) fn new(edge_nodes: &'a EdgeNodeList, rng: T) -> Self {
+        let weights = edge_nodes.iter().map(|en| f64::from(en.weight)).collect();
+
+        Self::with_weights(edge_nodes, weights, rng)
+    }
+
+    /// Chooses the edge nodes in proportion to the given `weights` instead of their own weights.
+    pub(super) fn with_weights(edge_nodes: &'a EdgeNodeList, weights: Vec<f64>, rng: T) -> Self {
+        let equal_weights = weights.windows(2).all(|w| w[0] == w[1]);
+
+        // there is no need to build an index if every edge node has the same share
+        let sampling = match weights.first() {
+            None => Sampling::Empty,
+            Some(weight) if equal_weights && *weight > 0.0 => Sampling::Uniform,
+            Some(_) => match WeightedIndex::new(weights) {
+                Ok(index) => Sampling::Weighted(index),
+                Err(e) => {
+                    warn!("Failed to choose edge nodes by weight: {}", e);
+                    Sampling::Empty
+                }
+            },
+        };
+
+        RndEdgeNodeUrlIter {
+            edge_nodes,
+            sampling,
+            rng,
+        }
+    }
+}
+
+impl<'a, T> Iterator for RndEdgeNodeUrlIter<'a, T>
+where
+    T: Rng,
+{
+    type Item = &'a Url;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let edge_node = match &self.sampling {
+            Sampling::Uniform => self.edge_nodes.choose(&mut self.rng),
+            Sampling::Weighted(index) => self.edge_nodes.get(index.sample(&mut self.rng)),
+            Sampling::Empty => None,
+        };
+
+        edge_node.map(|v| &v.url)
+    }
+}
+
+impl<'a, T> EdgeNodeSelector<'a> for RndEdgeNodeUrlIter<'a, T>
+where
+    T: Rng,
+{
+    fn select(&mut self, _uri: &str) -> Option<&'a Url> {
+        self.next()
+    }
+}
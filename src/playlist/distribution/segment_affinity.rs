@@ -0,0 +1,163 @@
+use super::{hash, DistributionStrategy, EdgeNodeSelector};
+use crate::edge_node_discovery::EdgeNodeList;
+use crate::playlist::RewriteContext;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edge_node_discovery::EdgeNode;
+    use crate::test_util::rewrite_context;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    fn edge_nodes(hosts: &[&str]) -> EdgeNodeList {
+        Arc::new(
+            hosts
+                .iter()
+                .map(|host| EdgeNode {
+                    url: Url::parse(&format!("https://{}", host)).unwrap(),
+                    group: String::from("test"),
+                    weight: 1,
+                    load: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn select(edge_nodes: &EdgeNodeList, uri: &str) -> Option<String> {
+        SegmentAffinityDistribution
+            .selector(edge_nodes, &rewrite_context())
+            .select(uri)
+            .and_then(|url| url.host_str().map(String::from))
+    }
+
+    #[test]
+    fn test_segment_stays_on_one_edge_node() {
+        let edge_nodes = edge_nodes(&["alpha.com", "beta.com", "gamma.com"]);
+
+        let first = select(&edge_nodes, "https://origin.com/live/23.ts");
+
+        assert!(first.is_some());
+        assert_eq!(first, select(&edge_nodes, "https://origin.com/live/23.ts"));
+        assert_eq!(
+            first,
+            select(&edge_nodes, "https://origin.com/live/23.ts?token=foo")
+        );
+    }
+
+    #[test]
+    fn test_relative_uris_are_resolved() {
+        let edge_nodes = edge_nodes(&["alpha.com", "beta.com", "gamma.com"]);
+        let select_in_stream = |stream: usize, uri: &str| {
+            let playlist_url =
+                Url::parse(&format!("https://origin.com/live/{}/index.m3u8", stream)).unwrap();
+            let context = RewriteContext {
+                playlist_url: &playlist_url,
+                ..rewrite_context()
+            };
+
+            SegmentAffinityDistribution
+                .selector(&edge_nodes, &context)
+                .select(uri)
+                .and_then(|url| url.host_str().map(String::from))
+        };
+
+        assert_eq!(
+            select_in_stream(1, "https://origin.com/live/1/23.ts"),
+            select_in_stream(1, "23.ts")
+        );
+
+        // the same segment name of different streams is spread over the edge nodes
+        let hosts: HashSet<_> = (0..30)
+            .map(|stream| select_in_stream(stream, "1.ts"))
+            .collect();
+
+        assert_eq!(3, hosts.len());
+    }
+
+    #[test]
+    fn test_only_segments_of_removed_edge_node_move() {
+        let all_edge_nodes = edge_nodes(&["alpha.com", "beta.com", "gamma.com"]);
+        let remaining_edge_nodes = edge_nodes(&["alpha.com", "gamma.com"]);
+
+        let mut moved = 0;
+
+        for i in 0..900 {
+            let uri = format!("https://origin.com/live/{}.ts", i);
+            let before = select(&all_edge_nodes, &uri).unwrap();
+            let after = select(&remaining_edge_nodes, &uri).unwrap();
+
+            if before != "beta.com" {
+                assert_eq!(before, after);
+            } else {
+                moved += 1;
+            }
+        }
+
+        assert!(moved > 200 && moved < 400, "{} segments moved", moved);
+    }
+}
+
+/// Chooses the edge node of a segment by rendezvous hashing its uri resolved against the
+/// playlist url, so that every viewer requests the same segment from the same edge node.
+///
+/// Each edge node scores the uri with a hash of its url and the uri, scaled by its weight,
+/// the highest score wins. If an edge node leaves the group only its segments move.
+pub struct SegmentAffinityDistribution;
+
+impl DistributionStrategy for SegmentAffinityDistribution {
+    fn selector<'a>(
+        &'a self,
+        edge_nodes: &'a EdgeNodeList,
+        context: &RewriteContext,
+    ) -> Box<dyn EdgeNodeSelector<'a> + 'a> {
+        Box::new(RendezvousSelector {
+            edge_nodes,
+            playlist_url: context.playlist_url.clone(),
+        })
+    }
+}
+
+struct RendezvousSelector<'a> {
+    edge_nodes: &'a EdgeNodeList,
+    playlist_url: Url,
+}
+
+impl<'a> RendezvousSelector<'a> {
+    /// Relative segment uris like `1.ts` are the same in every stream.
+    fn key(&self, uri: &str) -> String {
+        let mut url = match self.playlist_url.join(uri) {
+            Ok(url) => url,
+            Err(_) => return uri.to_string(),
+        };
+
+        // query parameters of the upstream may differ between requests, e.g. tokens
+        url.set_query(None);
+        url.set_fragment(None);
+
+        url.to_string()
+    }
+}
+
+impl<'a> EdgeNodeSelector<'a> for RendezvousSelector<'a> {
+    fn select(&mut self, uri: &str) -> Option<&'a Url> {
+        let key = self.key(uri);
+
+        self.edge_nodes
+            .iter()
+            .filter(|edge_node| edge_node.weight > 0)
+            .map(|edge_node| {
+                let hash = hash(&format!("{}#{}", edge_node.url, key));
+                // map the 53 most significant bits of the hash into (0, 1)
+                let point = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+
+                (f64::from(edge_node.weight) / -point.ln(), &edge_node.url)
+            })
+            .fold(None, |best: Option<(f64, &'a Url)>, candidate| match best {
+                Some(best) if best.0 >= candidate.0 => Some(best),
+                _ => Some(candidate),
+            })
+            .map(|(_, url)| url)
+    }
+}
@@ -0,0 +1,246 @@
+use super::{hash, DistributionStrategy, EdgeNodeSelector};
+use crate::edge_node_discovery::EdgeNodeList;
+use crate::playlist::RewriteContext;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edge_node_discovery::EdgeNode;
+
+    fn edge_nodes(hosts: &[&str]) -> EdgeNodeList {
+        Arc::new(
+            hosts
+                .iter()
+                .map(|host| EdgeNode {
+                    url: Url::parse(&format!("https://{}", host)).unwrap(),
+                    group: String::from("test"),
+                    weight: 1,
+                    load: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn select(
+        distribution: &SessionAffinityDistribution,
+        edge_nodes: &EdgeNodeList,
+        session_key: &str,
+    ) -> Url {
+        let context = RewriteContext {
+            node_group: "test",
+            session_key,
+            playlist_url: &Url::parse("https://upstream.com/live/playlist.m3u8").unwrap(),
+            span: None,
+            assignments: None,
+        };
+
+        distribution
+            .selector(edge_nodes, &context)
+            .select("/23.ts")
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_session_stays_on_one_edge_node() {
+        let distribution = SessionAffinityDistribution::new(64);
+        let edge_nodes = edge_nodes(&["alpha.com", "beta.com", "gamma.com"]);
+
+        let context = RewriteContext {
+            node_group: "test",
+            session_key: "stream/10.0.0.1",
+            playlist_url: &Url::parse("https://upstream.com/live/playlist.m3u8").unwrap(),
+            span: None,
+            assignments: None,
+        };
+        let mut selector = distribution.selector(&edge_nodes, &context);
+        let first = selector.select("/23.ts").unwrap();
+
+        assert_eq!(Some(first), selector.select("/24.ts"));
+        assert_eq!(
+            *first,
+            select(&distribution, &edge_nodes, "stream/10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_only_sessions_of_removed_edge_node_move() {
+        let distribution = SessionAffinityDistribution::new(64);
+        let all_edge_nodes = edge_nodes(&["alpha.com", "beta.com", "gamma.com", "delta.com"]);
+        let remaining_edge_nodes = edge_nodes(&["alpha.com", "beta.com", "delta.com"]);
+
+        let mut moved = 0;
+
+        for i in 0..1000 {
+            let session_key = format!("stream/10.0.{}.{}", i / 256, i % 256);
+            let before = select(&distribution, &all_edge_nodes, &session_key);
+            let after = select(&distribution, &remaining_edge_nodes, &session_key);
+
+            if before.host_str() != Some("gamma.com") {
+                assert_eq!(before, after);
+            } else {
+                moved += 1;
+            }
+        }
+
+        // every edge node gets a share of the sessions
+        assert!(moved > 100 && moved < 400, "{} sessions moved", moved);
+    }
+
+    #[test]
+    fn test_only_sessions_of_joining_heavier_edge_node_move() {
+        let distribution = SessionAffinityDistribution::new(64);
+        let edge_nodes_before = edge_nodes(&["alpha.com", "beta.com", "gamma.com"]);
+        let mut edge_nodes_after = edge_nodes(&["alpha.com", "beta.com", "gamma.com", "delta.com"]);
+        Arc::get_mut(&mut edge_nodes_after).unwrap()[3].weight = 3;
+
+        let mut moved = 0;
+
+        for i in 0..1000 {
+            let session_key = format!("stream/10.0.{}.{}", i / 256, i % 256);
+            let before = select(&distribution, &edge_nodes_before, &session_key);
+            let after = select(&distribution, &edge_nodes_after, &session_key);
+
+            if before != after {
+                assert_eq!(Some("delta.com"), after.host_str());
+                moved += 1;
+            }
+        }
+
+        // delta.com has half of the total weight
+        assert!(moved > 350 && moved < 650, "{} sessions moved", moved);
+    }
+
+    #[test]
+    fn test_points_of_heavy_edge_node_are_bounded() {
+        let mut edge_nodes = edge_nodes(&["alpha.com", "beta.com"]);
+        Arc::get_mut(&mut edge_nodes).unwrap()[1].weight = u32::MAX;
+
+        let ring = HashRing::new(&edge_nodes, 100);
+
+        assert_eq!(100 + MAX_EDGE_NODE_POINTS, ring.0.len());
+    }
+
+    #[test]
+    fn test_drained_edge_node_gets_no_sessions() {
+        let distribution = SessionAffinityDistribution::new(64);
+        let mut edge_nodes = edge_nodes(&["alpha.com", "beta.com"]);
+        Arc::get_mut(&mut edge_nodes).unwrap()[1].weight = 0;
+
+        for i in 0..100 {
+            let session_key = format!("stream/10.0.0.{}", i);
+
+            assert_eq!(
+                Some("alpha.com"),
+                select(&distribution, &edge_nodes, &session_key).host_str()
+            );
+        }
+    }
+}
+
+/// Keeps every viewer session on a single edge node, by placing the edge nodes
+/// and the session keys on a consistent hash ring.
+///
+/// Every edge node is put on the ring `virtual_nodes` times per unit of its weight to even out
+/// the distribution, but at most `MAX_EDGE_NODE_POINTS` times. If an edge node joins or leaves
+/// the group, or its weight changes, only the sessions of that node move.
+pub struct SessionAffinityDistribution {
+    virtual_nodes: usize,
+    rings: Mutex<HashMap<String, (EdgeNodeList, Arc<HashRing>)>>,
+}
+
+impl SessionAffinityDistribution {
+    pub fn new(virtual_nodes: usize) -> SessionAffinityDistribution {
+        SessionAffinityDistribution {
+            virtual_nodes: virtual_nodes.max(1),
+            rings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn ring(&self, edge_nodes: &EdgeNodeList, node_group: &str) -> Arc<HashRing> {
+        let mut rings = self.rings.lock().unwrap();
+
+        // the edge node provider hands out the same list until the edge nodes change
+        match rings.get(node_group) {
+            Some((cached_edge_nodes, ring)) if Arc::ptr_eq(cached_edge_nodes, edge_nodes) => {
+                Arc::clone(ring)
+            }
+            _ => {
+                let ring = Arc::new(HashRing::new(edge_nodes, self.virtual_nodes));
+                rings.insert(
+                    node_group.to_string(),
+                    (Arc::clone(edge_nodes), Arc::clone(&ring)),
+                );
+
+                ring
+            }
+        }
+    }
+}
+
+impl DistributionStrategy for SessionAffinityDistribution {
+    fn selector<'a>(
+        &'a self,
+        edge_nodes: &'a EdgeNodeList,
+        context: &RewriteContext,
+    ) -> Box<dyn EdgeNodeSelector<'a> + 'a> {
+        let edge_node = self
+            .ring(edge_nodes, context.node_group)
+            .find(context.session_key)
+            .map(|index| &edge_nodes[index].url);
+
+        Box::new(FixedEdgeNode(edge_node))
+    }
+}
+
+/// Bounds the time to build a ring for edge nodes with huge weights, it's built on the
+/// request path.
+const MAX_EDGE_NODE_POINTS: usize = 10_000;
+
+/// Points on the ring, sorted by their hash, pointing to the index of their edge node.
+struct HashRing(Vec<(u64, usize)>);
+
+impl HashRing {
+    fn new(edge_nodes: &EdgeNodeList, virtual_nodes: usize) -> HashRing {
+        let mut points: Vec<(u64, usize)> = edge_nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, edge_node)| {
+                // the points of a node don't depend on the other nodes
+                let node_points = virtual_nodes
+                    .saturating_mul(edge_node.weight as usize)
+                    .min(MAX_EDGE_NODE_POINTS);
+
+                (0..node_points)
+                    .map(move |vnode| (hash(&format!("{}#{}", edge_node.url, vnode)), index))
+            })
+            .collect();
+
+        points.sort_unstable();
+
+        HashRing(points)
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        let key_hash = hash(key);
+        let position = match self.0.binary_search_by_key(&key_hash, |(hash, _)| *hash) {
+            Ok(position) | Err(position) => position,
+        };
+
+        self.0
+            .get(position)
+            .or_else(|| self.0.first())
+            .map(|(_, index)| *index)
+    }
+}
+
+struct FixedEdgeNode<'a>(Option<&'a Url>);
+
+impl<'a> EdgeNodeSelector<'a> for FixedEdgeNode<'a> {
+    fn select(&mut self, _uri: &str) -> Option<&'a Url> {
+        self.0
+    }
+}
@@ -0,0 +1,233 @@
+use crate::config;
+use crate::playlist::segment_load_distributor::try_to_change_uri_host;
+use crate::playlist::{HmacUrlSigner, PlaylistRewriter, RewriteContext, UrlSigner};
+use hls_m3u8::types::DecryptionKey;
+use hls_m3u8::{MasterPlaylist, MediaPlaylist};
+use log::{error, warn};
+use regex::Regex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_segment, rewrite_context};
+    use hls_m3u8::tags::{ExtXKey, ExtXSessionKey};
+    use hls_m3u8::types::EncryptionMethod;
+
+    struct MockUrlSigner;
+    impl UrlSigner for MockUrlSigner {
+        fn sign(&self, mut url: Url, _expiry_timestamp: u64) -> Url {
+            url.query_pairs_mut().append_pair("foo", "bar");
+
+            url
+        }
+    }
+
+    fn rewriter() -> KeyUrlRewriter {
+        KeyUrlRewriter::new(vec![
+            KeyUrlRule::new(Regex::new("^https://keep.com/").unwrap()),
+            KeyUrlRule::new(Regex::new("^https://keys.internal/").unwrap())
+                .with_base_url(Url::parse("https://keys.example.com").unwrap())
+                .with_signer(Box::new(MockUrlSigner), Duration::from_secs(30)),
+        ])
+    }
+
+    fn build_key(uri: &'static str) -> DecryptionKey<'static> {
+        DecryptionKey::new(EncryptionMethod::Aes128, uri)
+    }
+
+    #[test]
+    fn test_key_url_rewriting() {
+        // setup test playlist
+        let mut segment = build_segment("http://example.com/23.ts");
+        segment.keys = vec![ExtXKey::new(build_key("https://keys.internal/23.key"))];
+
+        let mut unmatched_segment = build_segment("http://example.com/24.ts");
+        unmatched_segment.keys = vec![ExtXKey::new(build_key("https://other.com/24.key"))];
+
+        let mut kept_segment = build_segment("http://example.com/25.ts");
+        kept_segment.keys = vec![ExtXKey::new(build_key("https://keep.com/25.key"))];
+
+        let media_playlist = MediaPlaylist::builder()
+            .target_duration(Duration::from_secs(3))
+            .push_segment(segment)
+            .push_segment(unmatched_segment)
+            .push_segment(kept_segment)
+            .build()
+            .unwrap();
+
+        // rewrite
+        let media_playlist = rewriter().rewrite_playlist(media_playlist, &rewrite_context());
+        let uris: Vec<&str> = media_playlist
+            .segments
+            .values()
+            .flat_map(|seg| seg.keys.iter())
+            .filter_map(|key| key.as_ref().map(|dk| dk.uri().as_ref()))
+            .collect();
+
+        // assert
+        assert_eq!(
+            vec![
+                "https://keys.example.com/23.key?foo=bar",
+                "https://other.com/24.key",
+                "https://keep.com/25.key",
+            ],
+            uris
+        );
+    }
+
+    #[test]
+    fn test_session_key_url_rewriting() {
+        let master_playlist = MasterPlaylist::builder()
+            .session_keys(vec![ExtXSessionKey::new(build_key(
+                "https://keys.internal/session.key",
+            ))])
+            .build()
+            .unwrap();
+
+        let master_playlist = rewriter().rewrite_session_keys(master_playlist);
+
+        assert_eq!(
+            "https://keys.example.com/session.key?foo=bar",
+            master_playlist.session_keys[0].0.uri()
+        );
+    }
+}
+
+/// Decides how the uri of a decryption key is rewritten, if it matches the `pattern`.
+pub struct KeyUrlRule {
+    pattern: Regex,
+    base_url: Option<Url>,
+    signer: Option<(Box<dyn UrlSigner>, Duration)>,
+}
+
+impl KeyUrlRule {
+    pub fn new(pattern: Regex) -> KeyUrlRule {
+        KeyUrlRule {
+            pattern,
+            base_url: None,
+            signer: None,
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: Url) -> KeyUrlRule {
+        self.base_url = Some(base_url);
+        self
+    }
+
+    pub fn with_signer(
+        mut self,
+        signer: Box<dyn UrlSigner>,
+        expiry_duration: Duration,
+    ) -> KeyUrlRule {
+        self.signer = Some((signer, expiry_duration));
+        self
+    }
+
+    fn rewrite_uri(&self, uri: &str, now: SystemTime) -> Option<String> {
+        if self.base_url.is_none() && self.signer.is_none() {
+            return None;
+        }
+
+        let mut url = match &self.base_url {
+            Some(base_url) => try_to_change_uri_host(uri, base_url)
+                .map_err(|e| warn!("Failed to change key uri host: {}", e))
+                .ok()?,
+            None => Url::parse(uri)
+                .map_err(|e| warn!("Failed to parse URL: {} Err: {}", uri, e))
+                .ok()?,
+        };
+
+        if let Some((signer, expiry_duration)) = &self.signer {
+            match (now + *expiry_duration).duration_since(UNIX_EPOCH) {
+                Ok(valid_until) => url = signer.sign(url, valid_until.as_secs()),
+                Err(e) => {
+                    error!("Failed to get a valid expiry unix timestamp: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        Some(url.to_string())
+    }
+}
+
+impl From<&config::KeyDelivery> for KeyUrlRule {
+    fn from(value: &config::KeyDelivery) -> Self {
+        let mut rule = KeyUrlRule::new(value.uri_pattern.clone());
+
+        if let Some(base_url) = &value.base_url {
+            rule = rule.with_base_url(base_url.clone());
+        }
+
+        if let Some(signing) = &value.signing {
+            rule = rule.with_signer(
+                Box::new(HmacUrlSigner::new(signing.key.clone())),
+                signing.duration,
+            );
+        }
+
+        rule
+    }
+}
+
+/// Routes and signs the uris of decryption keys according to the first matching rule.
+/// Key uris without a matching rule are left alone.
+pub struct KeyUrlRewriter {
+    rules: Vec<KeyUrlRule>,
+}
+
+impl KeyUrlRewriter {
+    pub fn new(rules: Vec<KeyUrlRule>) -> KeyUrlRewriter {
+        KeyUrlRewriter { rules }
+    }
+
+    pub fn from_config(key_delivery: &[config::KeyDelivery]) -> KeyUrlRewriter {
+        KeyUrlRewriter::new(key_delivery.iter().map(KeyUrlRule::from).collect())
+    }
+
+    pub fn rewrite_session_keys<'a>(&self, mut playlist: MasterPlaylist<'a>) -> MasterPlaylist<'a> {
+        let now = SystemTime::now();
+
+        for session_key in playlist.session_keys.iter_mut() {
+            self.rewrite_key(&mut session_key.0, now);
+        }
+
+        playlist
+    }
+
+    fn rewrite_key(&self, key: &mut DecryptionKey, now: SystemTime) {
+        let rule = self.rules.iter().find(|r| r.pattern.is_match(key.uri()));
+
+        if let Some(uri) = rule.and_then(|r| r.rewrite_uri(key.uri(), now)) {
+            key.set_uri(uri);
+        }
+    }
+}
+
+impl PlaylistRewriter for KeyUrlRewriter {
+    fn name(&self) -> &'static str {
+        "key_url_rewriter"
+    }
+
+    fn rewrite_playlist<'a>(
+        &self,
+        mut playlist: MediaPlaylist<'a>,
+        _context: &RewriteContext,
+    ) -> MediaPlaylist<'a> {
+        // every segment carries a copy of its keys, use the same expiry for all of them
+        // so that identical keys stay identical after signing
+        let now = SystemTime::now();
+
+        for seg in playlist.segments.values_mut() {
+            for key in seg.keys.iter_mut() {
+                if let Some(decryption_key) = key.0.as_mut() {
+                    self.rewrite_key(decryption_key, now);
+                }
+            }
+        }
+
+        playlist
+    }
+}
@@ -0,0 +1,349 @@
+use std::ops::Range;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hls_m3u8::MediaPlaylist;
+
+    const LOW_LATENCY_PLAYLIST: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-VERSION:6
+#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.0
+#EXT-X-PART-INF:PART-TARGET=0.33334
+#EXT-X-MEDIA-SEQUENCE:266
+#EXTINF:4.00008,
+fileSequence266.mp4
+#EXT-X-PART:DURATION=0.33334,URI=\"filePart267.0.mp4\",INDEPENDENT=YES
+#EXT-X-PART:DURATION=0.33334,URI=\"filePart267.1.mp4\"
+#EXTINF:4.00008,
+fileSequence267.mp4
+#EXT-X-PROGRAM-DATE-TIME:2019-02-14T02:14:00.106Z
+#EXT-X-PART:DURATION=0.33334,URI=\"filePart268.0.mp4\",INDEPENDENT=YES
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"filePart268.1.mp4\"
+#EXT-X-RENDITION-REPORT:URI=\"../1M/waitForMSN.php\",LAST-MSN=268,LAST-PART=0
+";
+
+    const PART: &str = "#EXT-X-PART:DURATION=1,URI=\"2.0.ts\"\n";
+
+    #[test]
+    fn test_extract_and_insert_low_latency_tags() {
+        let (media_playlist, mut tags) = LowLatencyTags::extract(LOW_LATENCY_PLAYLIST);
+
+        let segment_indices: Vec<(usize, LowLatencyTagKind)> = tags
+            .iter_mut()
+            .map(|tag| (tag.segment_index(), tag.kind()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (1, LowLatencyTagKind::Part),
+                (1, LowLatencyTagKind::Part),
+                (2, LowLatencyTagKind::Verbatim),
+                (2, LowLatencyTagKind::Part),
+                (2, LowLatencyTagKind::PreloadHint),
+                (2, LowLatencyTagKind::RenditionReport),
+            ],
+            segment_indices
+        );
+
+        let media_playlist = media_playlist.parse::<MediaPlaylist>().unwrap();
+
+        for tag in tags.iter_mut() {
+            if let Some(uri) = tag.uri().map(|uri| format!("https://edge/{}", uri)) {
+                tag.set_uri(&uri);
+            }
+        }
+
+        assert_eq!(
+            "#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.0
+#EXT-X-PART-INF:PART-TARGET=0.33334
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:266
+#EXTINF:4.00008,
+fileSequence266.mp4
+#EXT-X-PART:DURATION=0.33334,URI=\"https://edge/filePart267.0.mp4\",INDEPENDENT=YES
+#EXT-X-PART:DURATION=0.33334,URI=\"https://edge/filePart267.1.mp4\"
+#EXTINF:4.00008,
+fileSequence267.mp4
+#EXT-X-PROGRAM-DATE-TIME:2019-02-14T02:14:00.106Z
+#EXT-X-PART:DURATION=0.33334,URI=\"https://edge/filePart268.0.mp4\",INDEPENDENT=YES
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"https://edge/filePart268.1.mp4\"
+#EXT-X-RENDITION-REPORT:URI=\"https://edge/../1M/waitForMSN.php\",LAST-MSN=268,LAST-PART=0
+",
+            tags.insert_into(&media_playlist.to_string())
+        );
+    }
+
+    #[test]
+    fn test_version_of_upstream_is_kept() {
+        let versions = vec![
+            ("#EXT-X-VERSION:9\n", "", "#EXT-X-VERSION:9"),
+            ("#EXT-X-VERSION:3\n", "", "#EXT-X-VERSION:3"),
+            ("#EXT-X-VERSION:3\n", PART, "#EXT-X-VERSION:6"),
+            ("", PART, "#EXT-X-VERSION:6"),
+        ];
+
+        for (version, part, expected) in versions {
+            let playlist = format!(
+                "#EXTM3U\n{}#EXT-X-TARGETDURATION:4\n#EXTINF:4,\n1.ts\n{}",
+                version, part
+            );
+            let (media_playlist, tags) = LowLatencyTags::extract(&playlist);
+            let media_playlist = media_playlist.parse::<MediaPlaylist>().unwrap();
+            let rewritten = tags.insert_into(&media_playlist.to_string());
+
+            assert_eq!(Some(expected), rewritten.lines().nth(1));
+            assert_eq!(
+                1,
+                rewritten
+                    .lines()
+                    .filter(|line| line.starts_with("#EXT-X-VERSION:"))
+                    .count()
+            );
+        }
+    }
+}
+
+const VERSION_PREFIX: &str = "#EXT-X-VERSION:";
+const HEADER_PREFIXES: [&str; 2] = ["#EXT-X-SERVER-CONTROL:", "#EXT-X-PART-INF:"];
+const PART_PREFIX: &str = "#EXT-X-PART:";
+const PRELOAD_HINT_PREFIX: &str = "#EXT-X-PRELOAD-HINT:";
+const RENDITION_REPORT_PREFIX: &str = "#EXT-X-RENDITION-REPORT:";
+const END_LIST_TAG: &str = "#EXT-X-ENDLIST";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LowLatencyTagKind {
+    Part,
+    PreloadHint,
+    RenditionReport,
+    /// Tags that belong to the segment which is still in progress,
+    /// they are kept as they are.
+    Verbatim,
+}
+
+#[derive(Debug)]
+pub struct LowLatencyTag {
+    segment_index: usize,
+    kind: LowLatencyTagKind,
+    line: String,
+}
+
+impl LowLatencyTag {
+    /// Index of the media segment this tag precedes, equals the number of media segments
+    /// for tags that belong to the segment which is still in progress.
+    pub fn segment_index(&self) -> usize {
+        self.segment_index
+    }
+
+    pub fn kind(&self) -> LowLatencyTagKind {
+        self.kind
+    }
+
+    pub fn uri(&self) -> Option<&str> {
+        match self.kind {
+            LowLatencyTagKind::Verbatim => None,
+            _ => uri_range(&self.line).map(|range| &self.line[range]),
+        }
+    }
+
+    pub fn set_uri(&mut self, uri: &str) {
+        if let Some(range) = self.uri().and_then(|_| uri_range(&self.line)) {
+            self.line.replace_range(range, uri);
+        }
+    }
+}
+
+/// Low-Latency HLS tags aren't supported by hls_m3u8, which would move them
+/// to the end of the playlist. They are taken out of the playlist before parsing it
+/// and put back in place after the playlist has been rewritten.
+///
+/// The version is taken out as well, hls_m3u8 would lower it to the version of the tags it knows.
+#[derive(Debug, Default)]
+pub struct LowLatencyTags {
+    version: Option<u32>,
+    header: Vec<String>,
+    tags: Vec<LowLatencyTag>,
+}
+
+impl LowLatencyTags {
+    /// Splits the given playlist into a playlist without Low-Latency HLS tags
+    /// and the removed tags.
+    pub fn extract(playlist: &str) -> (String, LowLatencyTags) {
+        let lines: Vec<&str> = playlist.lines().collect();
+        let last_uri_line = lines.iter().rposition(|line| is_uri_line(line));
+
+        let mut remaining = String::with_capacity(playlist.len());
+        let mut version = None;
+        let mut header = Vec::new();
+        let mut tags = Vec::new();
+        let mut segment_index = 0;
+
+        for (line_index, line) in lines.into_iter().enumerate() {
+            if let Some(v) = line.strip_prefix(VERSION_PREFIX) {
+                if let Ok(v) = v.trim().parse() {
+                    version = Some(v);
+                    continue;
+                }
+            }
+
+            if HEADER_PREFIXES
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+            {
+                header.push(line.to_string());
+                continue;
+            }
+
+            let in_trailing_segment = last_uri_line.is_some_and(|last| line_index > last);
+            let kind = if line.starts_with(PART_PREFIX) {
+                Some(LowLatencyTagKind::Part)
+            } else if line.starts_with(PRELOAD_HINT_PREFIX) {
+                Some(LowLatencyTagKind::PreloadHint)
+            } else if line.starts_with(RENDITION_REPORT_PREFIX) {
+                Some(LowLatencyTagKind::RenditionReport)
+            } else if in_trailing_segment && line.starts_with('#') && line != END_LIST_TAG {
+                Some(LowLatencyTagKind::Verbatim)
+            } else {
+                None
+            };
+
+            match kind {
+                Some(kind) => tags.push(LowLatencyTag {
+                    segment_index,
+                    kind,
+                    line: line.to_string(),
+                }),
+                None => {
+                    remaining.push_str(line);
+                    remaining.push('\n');
+                }
+            }
+
+            if is_uri_line(line) {
+                segment_index += 1;
+            }
+        }
+
+        let tags = LowLatencyTags {
+            version,
+            header,
+            tags,
+        };
+
+        (remaining, tags)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut LowLatencyTag> {
+        self.tags.iter_mut()
+    }
+
+    /// Puts the tags back into the given serialized playlist, header tags after `#EXTM3U`,
+    /// tags of a segment in front of its `#EXTINF` tag and tags of the segment in progress
+    /// after the last segment.
+    pub fn insert_into(&self, playlist: &str) -> String {
+        let segment_count = playlist.lines().filter(|line| is_uri_line(line)).count();
+        let version = self.version();
+
+        let mut result = String::with_capacity(playlist.len());
+        let mut tags = self.tags.iter().peekable();
+        let mut segment_index = 0;
+
+        let mut push_tags_until = |result: &mut String, segment_index: usize| {
+            while let Some(tag) = tags.peek() {
+                if tag.segment_index > segment_index {
+                    break;
+                }
+
+                result.push_str(&tag.line);
+                result.push('\n');
+                tags.next();
+            }
+        };
+
+        for line in playlist.lines() {
+            if line.starts_with(VERSION_PREFIX) && version.is_some() {
+                continue;
+            }
+
+            if line.starts_with("#EXTM3U") {
+                result.push_str(line);
+                result.push('\n');
+
+                if let Some(version) = version {
+                    result.push_str(&format!("{}{}\n", VERSION_PREFIX, version));
+                }
+
+                for tag in &self.header {
+                    result.push_str(tag);
+                    result.push('\n');
+                }
+
+                continue;
+            }
+
+            if line.starts_with("#EXTINF:") {
+                push_tags_until(&mut result, segment_index);
+            }
+
+            result.push_str(line);
+            result.push('\n');
+
+            if is_uri_line(line) {
+                segment_index += 1;
+
+                if segment_index == segment_count {
+                    push_tags_until(&mut result, usize::MAX);
+                }
+            }
+        }
+
+        if segment_count == 0 {
+            push_tags_until(&mut result, usize::MAX);
+        }
+
+        result
+    }
+
+    /// The version of the upstream, partial segments and preload hints require version 6.
+    fn version(&self) -> Option<u32> {
+        let low_latency = self.tags.iter().any(|tag| {
+            matches!(
+                tag.kind,
+                LowLatencyTagKind::Part | LowLatencyTagKind::PreloadHint
+            )
+        });
+
+        match (self.version, low_latency) {
+            (Some(version), true) => Some(version.max(6)),
+            (None, true) => Some(6),
+            (version, false) => version,
+        }
+    }
+}
+
+fn is_uri_line(line: &str) -> bool {
+    let line = line.trim();
+
+    !line.is_empty() && !line.starts_with('#')
+}
+
+fn uri_range(line: &str) -> Option<Range<usize>> {
+    let mut search_from = 0;
+
+    while let Some(pos) = line[search_from..].find("URI=\"") {
+        let start = search_from + pos;
+        let value_start = start + "URI=\"".len();
+
+        if start > 0 && matches!(line.as_bytes()[start - 1], b':' | b',') {
+            let value_len = line[value_start..].find('"')?;
+
+            return Some(value_start..value_start + value_len);
+        }
+
+        search_from = value_start;
+    }
+
+    None
+}
@@ -0,0 +1,205 @@
+use crate::playlist::{KeyUrlRewriter, LowLatencyTagKind, LowLatencyTags};
+use hls_m3u8::tags::VariantStream;
+use hls_m3u8::MasterPlaylist;
+use log::warn;
+use std::borrow::Cow;
+use std::fmt;
+use url::{form_urlencoded, ParseError, Url};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const MASTER_PLAYLIST: &str = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"en\",URI=\"audio/en.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=1280000,AUDIO=\"aud\"
+low/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2560000,AUDIO=\"aud\"
+https://upstream.com/live/high/index.m3u8?token=abc%2F1
+#EXT-X-STREAM-INF:BANDWIDTH=7680000,AUDIO=\"aud\"
+https://elsewhere.com/live/max/index.m3u8
+#EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=86000,URI=\"/live/low/iframe.m3u8\"
+";
+
+    #[test]
+    fn test_master_playlist_rewriting() {
+        let master_playlist = MasterPlaylist::try_from(MASTER_PLAYLIST).unwrap();
+        let rewriter =
+            MasterPlaylistRewriter::new(Url::parse("https://upstream.com").unwrap(), "/playlist/");
+
+        let master_playlist = rewriter.rewrite_playlist(
+            master_playlist,
+            &Url::parse("https://upstream.com/live/master.m3u8").unwrap(),
+            "foo.bar",
+        );
+
+        let media_uris: Vec<Option<&Cow<str>>> =
+            master_playlist.media.iter().map(|m| m.uri()).collect();
+
+        assert_eq!(
+            vec![Some(&Cow::from("/playlist/live/audio/en.m3u8?jwt=foo.bar"))],
+            media_uris
+        );
+
+        let variant_uris: Vec<&str> = master_playlist
+            .variant_streams
+            .iter()
+            .map(|vs| match vs {
+                VariantStream::ExtXIFrame { uri, .. } => uri.as_ref(),
+                VariantStream::ExtXStreamInf { uri, .. } => uri.as_ref(),
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                "/playlist/live/low/index.m3u8?jwt=foo.bar",
+                "/playlist/live/high/index.m3u8?token=abc%2F1&jwt=foo.bar",
+                "https://elsewhere.com/live/max/index.m3u8",
+                "/playlist/live/low/iframe.m3u8?jwt=foo.bar",
+            ],
+            variant_uris
+        );
+    }
+}
+
+/// Rewrites the uris of variant streams, i-frame streams and renditions of a master playlist,
+/// so that the referenced media playlists are requested through this service again.
+pub struct MasterPlaylistRewriter {
+    upstream_base_url: Url,
+    proxy_path: String,
+    session_key_rewriter: Option<KeyUrlRewriter>,
+}
+
+impl MasterPlaylistRewriter {
+    pub fn new(upstream_base_url: Url, proxy_path: impl Into<String>) -> MasterPlaylistRewriter {
+        MasterPlaylistRewriter {
+            upstream_base_url,
+            proxy_path: proxy_path.into(),
+            session_key_rewriter: None,
+        }
+    }
+
+    pub fn with_session_key_rewriter(
+        mut self,
+        session_key_rewriter: KeyUrlRewriter,
+    ) -> MasterPlaylistRewriter {
+        self.session_key_rewriter = Some(session_key_rewriter);
+        self
+    }
+
+    pub fn rewrite_playlist<'a>(
+        &self,
+        mut playlist: MasterPlaylist<'a>,
+        upstream_playlist_url: &Url,
+        jwt: &str,
+    ) -> MasterPlaylist<'a> {
+        for media in playlist.media.iter_mut() {
+            let proxy_uri = media
+                .uri()
+                .and_then(|uri| self.try_to_build_proxy_uri(uri, upstream_playlist_url, jwt));
+
+            if proxy_uri.is_some() {
+                media.set_uri(proxy_uri);
+            }
+        }
+
+        for variant_stream in playlist.variant_streams.iter_mut() {
+            let uri = match variant_stream {
+                VariantStream::ExtXIFrame { uri, .. } => uri,
+                VariantStream::ExtXStreamInf { uri, .. } => uri,
+            };
+
+            if let Some(proxy_uri) = self.try_to_build_proxy_uri(uri, upstream_playlist_url, jwt) {
+                *uri = Cow::Owned(proxy_uri);
+            }
+        }
+
+        match &self.session_key_rewriter {
+            Some(session_key_rewriter) => session_key_rewriter.rewrite_session_keys(playlist),
+            None => playlist,
+        }
+    }
+
+    /// Rendition reports reference media playlists of other renditions,
+    /// those have to be requested through this service as well.
+    pub fn rewrite_rendition_reports(
+        &self,
+        tags: &mut LowLatencyTags,
+        upstream_playlist_url: &Url,
+        jwt: &str,
+    ) {
+        let rendition_reports = tags
+            .iter_mut()
+            .filter(|tag| tag.kind() == LowLatencyTagKind::RenditionReport);
+
+        for tag in rendition_reports {
+            let proxy_uri = tag
+                .uri()
+                .and_then(|uri| self.try_to_build_proxy_uri(uri, upstream_playlist_url, jwt));
+
+            if let Some(proxy_uri) = proxy_uri {
+                tag.set_uri(&proxy_uri);
+            }
+        }
+    }
+
+    fn try_to_build_proxy_uri(
+        &self,
+        uri: &str,
+        upstream_playlist_url: &Url,
+        jwt: &str,
+    ) -> Option<String> {
+        self.build_proxy_uri(uri, upstream_playlist_url, jwt)
+            .map_err(|e| warn!("Failed to rewrite variant uri `{}`: {}", uri, e))
+            .ok()
+    }
+
+    fn build_proxy_uri(
+        &self,
+        uri: &str,
+        upstream_playlist_url: &Url,
+        jwt: &str,
+    ) -> Result<String, ProxyUriErr> {
+        let upstream_base_url = self.upstream_base_url.join(".")?;
+        let variant_url = upstream_playlist_url.join(uri)?;
+
+        if variant_url.origin() != upstream_base_url.origin()
+            || !variant_url.path().starts_with(upstream_base_url.path())
+        {
+            return Err(ProxyUriErr::OutsideUpstream(variant_url));
+        }
+
+        let path = &variant_url.path()[upstream_base_url.path().len()..];
+        // keep the query of the upstream, e.g. cdn tokens, it's passed on to the upstream again
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(variant_url.query_pairs())
+            .append_pair("jwt", jwt)
+            .finish();
+
+        Ok(format!("{}{}?{}", self.proxy_path, path, query))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ProxyUriErr {
+    OutsideUpstream(Url),
+    UrlParse(ParseError),
+}
+
+impl fmt::Display for ProxyUriErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProxyUriErr::OutsideUpstream(url) => {
+                write!(f, "`{}` is not located below the upstream base url", url)
+            }
+            ProxyUriErr::UrlParse(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<ParseError> for ProxyUriErr {
+    fn from(e: ParseError) -> Self {
+        ProxyUriErr::UrlParse(e)
+    }
+}
@@ -1,27 +1,32 @@
 use crate::edge_node_discovery::{EdgeNodeList, EdgeNodeProvider};
-use crate::playlist::PlaylistRewriter;
-use hls_m3u8::{MediaPlaylist, MediaSegment};
+use crate::metrics;
+use crate::playlist::{
+    DistributionStrategy, LowLatencyTagKind, LowLatencyTags, PlaylistRewriter, RewriteContext,
+};
+use hls_m3u8::MediaPlaylist;
 use log::warn;
-use rand::seq::SliceRandom;
-use rand::Rng;
 use std::fmt;
+use std::sync::Arc;
 use url::{ParseError, Url};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::playlist::{Assignments, LowLatencyTags, RandomDistribution};
+    use hls_m3u8::tags::ExtXMap;
     use hls_m3u8::MediaPlaylist;
     use std::borrow::Cow;
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
     use url::Url;
 
     use rand::rngs::mock::StepRng;
+    use rand::seq::SliceRandom;
     use rand::thread_rng;
     use rand::Rng;
 
     use crate::edge_node_discovery::{EdgeNode, EdgeNodeList};
-    use crate::test_util::build_segment;
+    use crate::test_util::{build_segment, rewrite_context};
 
     struct MockEdgeNodeProvider {
         edge_nodes: Vec<Url>,
@@ -35,6 +40,8 @@ mod tests {
                     .map(|v| EdgeNode {
                         url: v.clone(),
                         group: String::from(node_group),
+                        weight: 1,
+                        load: None,
                     })
                     .collect(),
             )
@@ -67,11 +74,18 @@ mod tests {
             MockEdgeNodeProvider {
                 edge_nodes: edge_nodes.clone(),
             },
-            Box::new(move || distri_rng.clone()),
+            Arc::new(RandomDistribution::new(Box::new(move || {
+                distri_rng.clone()
+            }))),
         );
 
         // rewrite
-        let media_playlist = distributor.rewrite_playlist(media_playlist, "test");
+        let assignments = Assignments::default();
+        let context = RewriteContext {
+            assignments: Some(&assignments),
+            ..rewrite_context()
+        };
+        let media_playlist = distributor.rewrite_playlist(media_playlist, &context);
         let uris: Vec<Cow<str>> = media_playlist
             .segments
             .values()
@@ -79,7 +93,7 @@ mod tests {
             .collect();
 
         // assert
-        let file_endings = vec!["/23.ts", "/24.ts", "/25.ts"];
+        let file_endings = ["/23.ts", "/24.ts", "/25.ts"];
 
         let expected: Vec<String> = file_endings
             .iter()
@@ -93,67 +107,238 @@ mod tests {
             })
             .collect();
 
-        assert_eq!(expected, uris)
+        assert_eq!(expected, uris);
+        assert_eq!(3, assignments.segments());
+        assert!(assignments.edge_nodes().iter().all(|edge_node| expected
+            .iter()
+            .any(|uri| uri.starts_with(edge_node.as_str()))));
     }
-}
 
-type RngProvider<U> = Box<dyn Fn() -> U + Send + Sync>;
+    #[test]
+    fn test_init_section_distribution() {
+        // setup test playlist
+        let mut segment = build_segment("http://example.com/23.m4s");
+        segment.map = Some(ExtXMap::new("http://example.com/init.mp4"));
+
+        let media_playlist = MediaPlaylist::builder()
+            .target_duration(Duration::from_secs(3))
+            .push_segment(segment)
+            .build()
+            .unwrap();
+
+        // setup distributor
+        let distributor = SegmentLoadDistributor::new(
+            MockEdgeNodeProvider {
+                edge_nodes: vec![Url::parse("https://alpha.com:2323").unwrap()],
+            },
+            Arc::new(RandomDistribution::new(Box::new(|| StepRng::new(0, 1)))),
+        );
+
+        // rewrite
+        let media_playlist = distributor.rewrite_playlist(media_playlist, &rewrite_context());
+        let segment = media_playlist.segments.values().next().unwrap();
+
+        // assert
+        assert_eq!("https://alpha.com:2323/23.m4s", segment.uri());
+        assert_eq!(
+            "https://alpha.com:2323/init.mp4",
+            segment.map.as_ref().unwrap().uri()
+        );
+    }
+
+    #[test]
+    fn test_partial_segment_distribution() {
+        let (media_playlist, mut tags) = LowLatencyTags::extract(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-PART:DURATION=0.5,URI=\"/23.0.mp4\"
+#EXTINF:1,
+/23.mp4
+#EXT-X-PART:DURATION=0.5,URI=\"/24.0.mp4\"
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"/24.1.mp4\"
+",
+        );
+        let media_playlist = media_playlist.parse::<MediaPlaylist>().unwrap();
+
+        let edge_nodes = vec![
+            Url::parse("https://alpha.com:2323").unwrap(),
+            Url::parse("https://beta.com").unwrap(),
+        ];
+
+        // setup distributor, the first rng chooses alpha for the segment,
+        // the second one chooses beta for the segment in progress
+        let rng_values = Mutex::new(vec![1 << 31, 0]);
+        let distributor = SegmentLoadDistributor::new(
+            MockEdgeNodeProvider { edge_nodes },
+            Arc::new(RandomDistribution::new(Box::new(move || {
+                StepRng::new(rng_values.lock().unwrap().pop().unwrap(), 0)
+            }))),
+        );
+
+        // rewrite
+        let media_playlist = distributor.rewrite_playlist(media_playlist, &rewrite_context());
+        distributor.rewrite_low_latency_tags(&media_playlist, &mut tags, &rewrite_context());
+        let uris: Vec<String> = tags
+            .iter_mut()
+            .filter_map(|tag| tag.uri().map(String::from))
+            .collect();
+
+        // assert
+        assert_eq!(
+            "https://alpha.com:2323/23.mp4",
+            media_playlist.segments.values().next().unwrap().uri()
+        );
+        assert_eq!(
+            vec![
+                "https://alpha.com:2323/23.0.mp4",
+                "https://beta.com/24.0.mp4",
+                "https://beta.com/24.1.mp4",
+            ],
+            uris
+        );
+    }
+}
 
-pub struct SegmentLoadDistributor<T, U>
+pub struct SegmentLoadDistributor<T>
 where
     T: EdgeNodeProvider,
-    U: Rng + Send + Sync,
 {
     edge_node_provider: T,
-    rng_provider: RngProvider<U>,
+    strategy: Arc<dyn DistributionStrategy>,
 }
 
-impl<T, U> SegmentLoadDistributor<T, U>
+impl<T> SegmentLoadDistributor<T>
 where
     T: EdgeNodeProvider,
-    U: Rng + Send + Sync,
 {
     pub fn new(
         edge_node_provider: T,
-        rng_provider: RngProvider<U>,
-    ) -> SegmentLoadDistributor<T, U> {
+        strategy: Arc<dyn DistributionStrategy>,
+    ) -> SegmentLoadDistributor<T> {
         SegmentLoadDistributor {
             edge_node_provider,
-            rng_provider,
+            strategy,
         }
     }
 }
 
-impl<T, U> PlaylistRewriter for SegmentLoadDistributor<T, U>
+impl<T> PlaylistRewriter for SegmentLoadDistributor<T>
 where
     T: EdgeNodeProvider,
-    U: Rng + Send + Sync,
 {
+    fn name(&self) -> &'static str {
+        "segment_load_distributor"
+    }
+
     fn rewrite_playlist<'a>(
         &self,
         mut playlist: MediaPlaylist<'a>,
-        node_group: &str,
+        context: &RewriteContext,
     ) -> MediaPlaylist<'a> {
-        let edge_nodes = self.edge_node_provider.get_edge_nodes(node_group);
-        let rnd_edge_node_iter = RndEdgeNodeUrlIter::new(&edge_nodes, (self.rng_provider)());
+        let edge_nodes = self.edge_node_provider.get_edge_nodes(context.node_group);
+        let mut selector = self.strategy.selector(&edge_nodes, context);
 
-        let edge_node_seg_iter = rnd_edge_node_iter.zip(playlist.segments.values_mut());
+        for seg in playlist.segments.values_mut() {
+            let edge_node = match selector.select(seg.uri()) {
+                Some(edge_node) => edge_node,
+                None => break,
+            };
 
-        for (edge_node, seg) in edge_node_seg_iter {
-            match try_to_change_segment_uri_host(&seg, &edge_node) {
+            metrics::ASSIGNED_SEGMENTS
+                .with_label_values(&[context.node_group, edge_node.as_str()])
+                .inc();
+
+            if let Some(assignments) = context.assignments {
+                assignments.assign_segment(edge_node);
+            }
+
+            match try_to_change_uri_host(seg.uri(), edge_node) {
                 Ok(uri) => {
-                    seg.set_uri(uri);
+                    seg.set_uri(uri.to_string());
                 }
                 Err(e) => warn!("Failed to change segment uri host: {}", e),
             }
+
+            if let Some(map) = seg.map.as_mut() {
+                match try_to_change_uri_host(map.uri(), edge_node) {
+                    Ok(uri) => {
+                        map.set_uri(uri.to_string());
+                    }
+                    Err(e) => warn!("Failed to change init section uri host: {}", e),
+                }
+            }
         }
 
         playlist
     }
+
+    fn rewrite_low_latency_tags(
+        &self,
+        playlist: &MediaPlaylist,
+        tags: &mut LowLatencyTags,
+        context: &RewriteContext,
+    ) {
+        let edge_nodes = self.edge_node_provider.get_edge_nodes(context.node_group);
+        let mut selector = self.strategy.selector(&edge_nodes, context);
+
+        // partial segments are sent to the edge node of their parent segment
+        let parent_edge_nodes: Vec<Option<&Url>> = playlist
+            .segments
+            .values()
+            .map(|seg| find_edge_node_of_uri(&edge_nodes, seg.uri()))
+            .collect();
+
+        // the parent of the partial segments in progress isn't known yet, choose a single node
+        let mut in_progress_edge_node = None;
+
+        let partial_segment_tags = tags.iter_mut().filter(|tag| {
+            matches!(
+                tag.kind(),
+                LowLatencyTagKind::Part | LowLatencyTagKind::PreloadHint
+            )
+        });
+
+        for tag in partial_segment_tags {
+            let uri = match tag.uri() {
+                Some(uri) => uri,
+                None => continue,
+            };
+
+            let edge_node = match parent_edge_nodes.get(tag.segment_index()) {
+                Some(edge_node) => *edge_node,
+                None => *in_progress_edge_node.get_or_insert_with(|| selector.select(uri)),
+            };
+
+            let uri = match edge_node {
+                Some(edge_node) => {
+                    if let Some(assignments) = context.assignments {
+                        assignments.add_edge_node(edge_node);
+                    }
+
+                    try_to_change_uri_host(uri, edge_node)
+                }
+                None => continue,
+            };
+
+            match uri {
+                Ok(uri) => tag.set_uri(uri.as_str()),
+                Err(e) => warn!("Failed to change partial segment uri host: {}", e),
+            }
+        }
+    }
+}
+
+fn find_edge_node_of_uri<'a>(edge_nodes: &'a EdgeNodeList, uri: &str) -> Option<&'a Url> {
+    let url = Url::parse(uri).ok()?;
+
+    edge_nodes
+        .iter()
+        .map(|en| &en.url)
+        .find(|en_url| en_url.origin() == url.origin())
 }
 
 #[derive(Debug, Clone)]
-enum HostChangeErr {
+pub(super) enum HostChangeErr {
     Scheme(String),
     Host(Option<String>),
     Port(Option<u16>),
@@ -183,11 +368,8 @@ impl From<ParseError> for HostChangeErr {
     }
 }
 
-fn try_to_change_segment_uri_host(
-    seg: &MediaSegment,
-    edge_node: &Url,
-) -> Result<String, HostChangeErr> {
-    let mut seg_uri = edge_node.join(seg.uri())?;
+pub(super) fn try_to_change_uri_host(uri: &str, edge_node: &Url) -> Result<Url, HostChangeErr> {
+    let mut seg_uri = edge_node.join(uri)?;
 
     seg_uri
         .set_scheme(edge_node.scheme())
@@ -201,27 +383,5 @@ fn try_to_change_segment_uri_host(
         .set_port(edge_node.port())
         .map_err(|_| HostChangeErr::Port(edge_node.port()))?;
 
-    Ok(seg_uri.into_string())
-}
-
-struct RndEdgeNodeUrlIter<'a, T: Rng> {
-    edge_nodes: &'a EdgeNodeList,
-    rng: T,
-}
-
-impl<'a, T: Rng> RndEdgeNodeUrlIter<'a, T> {
-    fn new(edge_nodes: &'a EdgeNodeList, rng: T) -> Self {
-        RndEdgeNodeUrlIter { edge_nodes, rng }
-    }
-}
-
-impl<'a, T> Iterator for RndEdgeNodeUrlIter<'a, T>
-where
-    T: Rng,
-{
-    type Item = &'a Url;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.edge_nodes.choose(&mut self.rng).map(|v| &v.url)
-    }
+    Ok(seg_uri)
 }
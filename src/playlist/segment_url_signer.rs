@@ -1,4 +1,4 @@
-use crate::playlist::PlaylistRewriter;
+use crate::playlist::{LowLatencyTagKind, LowLatencyTags, PlaylistRewriter, RewriteContext};
 use hls_m3u8::MediaPlaylist;
 use hmac::{Hmac, Mac, NewMac};
 use log::{error, warn};
@@ -9,7 +9,9 @@ use url::Url;
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_util::build_segment;
+    use crate::playlist::LowLatencyTags;
+    use crate::test_util::{build_segment, rewrite_context};
+    use hls_m3u8::tags::ExtXMap;
     use std::borrow::Cow;
 
     struct MockUrlSigner;
@@ -29,7 +31,7 @@ mod tests {
 
         assert_eq!(
             "https://example.com/23.ts?e=23&h=5QMKWR0t2SP5DSlgCwwC5FjAvDRLGtjrcaJs9jaYi2I%3D",
-            url.into_string()
+            url.to_string()
         );
     }
 
@@ -50,7 +52,7 @@ mod tests {
         };
 
         // rewrite
-        let media_playlist = signer.rewrite_playlist(media_playlist, "test");
+        let media_playlist = signer.rewrite_playlist(media_playlist, &rewrite_context());
         let uris: Vec<Cow<str>> = media_playlist
             .segments
             .values()
@@ -66,6 +68,74 @@ mod tests {
             uris
         )
     }
+
+    #[test]
+    fn test_init_section_url_signer() {
+        // setup test playlist
+        let mut segment = build_segment("http://example.com/23.m4s");
+        segment.map = Some(ExtXMap::new("http://example.com/init.mp4"));
+
+        let media_playlist = MediaPlaylist::builder()
+            .target_duration(Duration::from_secs(3))
+            .push_segment(segment)
+            .build()
+            .unwrap();
+
+        // setup signer
+        let signer = SegmentUrlSigner {
+            signer: MockUrlSigner,
+            expiry_duration: Duration::from_secs(3),
+        };
+
+        // rewrite
+        let media_playlist = signer.rewrite_playlist(media_playlist, &rewrite_context());
+        let segment = media_playlist.segments.values().next().unwrap();
+
+        // assert
+        assert_eq!("http://example.com/23.m4s?foo=bar", segment.uri());
+        assert_eq!(
+            "http://example.com/init.mp4?foo=bar",
+            segment.map.as_ref().unwrap().uri()
+        );
+    }
+
+    #[test]
+    fn test_partial_segment_url_signer() {
+        let (media_playlist, mut tags) = LowLatencyTags::extract(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXTINF:1,
+http://example.com/23.mp4
+#EXT-X-PART:DURATION=0.5,URI=\"http://example.com/24.0.mp4\"
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"http://example.com/24.1.mp4\"
+#EXT-X-RENDITION-REPORT:URI=\"../low/index.m3u8\",LAST-MSN=24
+",
+        );
+        let media_playlist = media_playlist.parse::<MediaPlaylist>().unwrap();
+
+        // setup signer
+        let signer = SegmentUrlSigner {
+            signer: MockUrlSigner,
+            expiry_duration: Duration::from_secs(3),
+        };
+
+        // rewrite
+        signer.rewrite_low_latency_tags(&media_playlist, &mut tags, &rewrite_context());
+        let uris: Vec<String> = tags
+            .iter_mut()
+            .filter_map(|tag| tag.uri().map(String::from))
+            .collect();
+
+        // assert
+        assert_eq!(
+            vec![
+                "http://example.com/24.0.mp4?foo=bar",
+                "http://example.com/24.1.mp4?foo=bar",
+                "../low/index.m3u8",
+            ],
+            uris
+        );
+    }
 }
 
 pub trait UrlSigner: Send + Sync {
@@ -93,7 +163,7 @@ impl UrlSigner for HmacUrlSigner {
         let mut content_to_sign = String::from(url.path());
         content_to_sign.push_str(&expiry_timestamp.to_string());
 
-        hmac.update(&content_to_sign.as_bytes());
+        hmac.update(content_to_sign.as_bytes());
         let signature = hmac.finalize();
         let signature = base64::encode(signature.into_bytes());
 
@@ -123,40 +193,84 @@ where
             expiry_duration,
         }
     }
+
+    fn valid_until(&self) -> Option<u64> {
+        match (SystemTime::now() + self.expiry_duration).duration_since(UNIX_EPOCH) {
+            Ok(valid_until) => Some(valid_until.as_secs()),
+            Err(e) => {
+                error!("Failed to get a valid expiry unix timestamp: {}", e);
+                None
+            }
+        }
+    }
+
+    fn try_to_sign(&self, uri: &str, valid_until: u64) -> Option<Url> {
+        match Url::parse(uri) {
+            Ok(url) => Some(self.signer.sign(url, valid_until)),
+            Err(e) => {
+                warn!("Failed to parse URL: {} Err: {}", uri, e);
+                None
+            }
+        }
+    }
 }
 
 impl<T> PlaylistRewriter for SegmentUrlSigner<T>
 where
     T: UrlSigner,
 {
+    fn name(&self) -> &'static str {
+        "segment_url_signer"
+    }
+
     fn rewrite_playlist<'a>(
         &self,
         mut playlist: MediaPlaylist<'a>,
-        _node_group: &str,
+        _context: &RewriteContext,
     ) -> MediaPlaylist<'a> {
-        let valid_until = (SystemTime::now() + self.expiry_duration).duration_since(UNIX_EPOCH);
-
         // skip playlist modification if we cant get a valid expiry unix timestamp
-        if valid_until.is_err() {
-            error!(
-                "Failed to get a valid expiry unix timestamp: {}",
-                valid_until.unwrap_err()
-            );
-            return playlist;
-        }
-
-        let valid_until = valid_until.unwrap().as_secs();
+        let valid_until = match self.valid_until() {
+            Some(valid_until) => valid_until,
+            None => return playlist,
+        };
 
         for seg in playlist.segments.values_mut() {
-            match Url::parse(seg.uri()) {
-                Ok(url) => {
-                    let signed_url = self.signer.sign(url, valid_until);
-                    seg.set_uri(signed_url.into_string());
+            if let Some(signed_url) = self.try_to_sign(seg.uri(), valid_until) {
+                seg.set_uri(signed_url.to_string());
+            }
+
+            if let Some(map) = seg.map.as_mut() {
+                if let Some(signed_url) = self.try_to_sign(map.uri(), valid_until) {
+                    map.set_uri(signed_url.to_string());
                 }
-                Err(e) => warn!("Failed to parse URL: {} Err: {}", seg.uri(), e),
             }
         }
 
         playlist
     }
+
+    fn rewrite_low_latency_tags(
+        &self,
+        _playlist: &MediaPlaylist,
+        tags: &mut LowLatencyTags,
+        _context: &RewriteContext,
+    ) {
+        let valid_until = match self.valid_until() {
+            Some(valid_until) => valid_until,
+            None => return,
+        };
+
+        let partial_segment_tags = tags.iter_mut().filter(|tag| {
+            matches!(
+                tag.kind(),
+                LowLatencyTagKind::Part | LowLatencyTagKind::PreloadHint
+            )
+        });
+
+        for tag in partial_segment_tags {
+            if let Some(signed_url) = tag.uri().and_then(|uri| self.try_to_sign(uri, valid_until)) {
+                tag.set_uri(signed_url.as_str());
+            }
+        }
+    }
 }
@@ -1,10 +1,27 @@
+use crate::playlist::RewriteContext;
 use hls_m3u8::MediaSegment;
+use lazy_static::lazy_static;
 use std::time::Duration;
+use url::Url;
 
-pub fn build_segment(uri: &'static str) -> MediaSegment {
+lazy_static! {
+    static ref PLAYLIST_URL: Url = Url::parse("https://upstream.com/live/playlist.m3u8").unwrap();
+}
+
+pub fn build_segment(uri: &'static str) -> MediaSegment<'static> {
     MediaSegment::builder()
         .duration(Duration::from_secs(3))
         .uri(uri)
         .build()
         .unwrap()
 }
+
+pub fn rewrite_context() -> RewriteContext<'static> {
+    RewriteContext {
+        node_group: "test",
+        session_key: "test-session",
+        playlist_url: &PLAYLIST_URL,
+        span: None,
+        assignments: None,
+    }
+}
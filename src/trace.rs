@@ -0,0 +1,427 @@
+mod otlp;
+
+use crate::config;
+use lazy_static::lazy_static;
+use log::{error, info};
+use reqwest::Client;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::time;
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let context = TraceContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )
+        .unwrap();
+
+        assert_eq!(
+            "0af7651916cd43dd8448eb211c80319c",
+            otlp::hex(&context.trace_id)
+        );
+        assert_eq!("b7ad6b7169203331", otlp::hex(&context.span_id));
+        assert!(context.sampled);
+        assert_eq!(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            context.traceparent()
+        );
+
+        let invalid = [
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319x-b7ad6b7169203331-01",
+        ];
+
+        for traceparent in invalid.iter() {
+            assert_eq!(None, TraceContext::from_traceparent(traceparent));
+        }
+    }
+
+    #[test]
+    fn test_sampling_ratio() {
+        let tracer = |sampling_ratio| Tracer {
+            queue: None,
+            sampling_ratio,
+        };
+        let sampled_parent = TraceContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        )
+        .unwrap();
+
+        assert!(
+            !tracer(0.0)
+                .start_span("root", SpanKind::Server, None)
+                .context()
+                .sampled
+        );
+        assert!(
+            tracer(1.0)
+                .start_span("root", SpanKind::Server, None)
+                .context()
+                .sampled
+        );
+        assert!(
+            tracer(0.0)
+                .start_span("child", SpanKind::Server, Some(&sampled_parent))
+                .context()
+                .sampled
+        );
+        assert!(
+            !Tracer::disabled()
+                .start_span("root", SpanKind::Server, None)
+                .context()
+                .sampled
+        );
+
+        let sampled = (0..1000)
+            .filter(|_| {
+                tracer(0.25)
+                    .start_span("root", SpanKind::Server, None)
+                    .context()
+                    .sampled
+            })
+            .count();
+        assert!(sampled > 150 && sampled < 350, "{} traces sampled", sampled);
+    }
+
+    #[test]
+    fn test_spans_are_queued() {
+        let queue = Arc::new(SpanQueue::new(2));
+        let tracer = Tracer {
+            queue: Some(Arc::clone(&queue)),
+            sampling_ratio: 1.0,
+        };
+        let parent = TraceContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00",
+        )
+        .unwrap();
+
+        let span = tracer.start_span("root", SpanKind::Server, None);
+        span.child("child", SpanKind::Client)
+            .set_error("upstream failed");
+        drop(span);
+        tracer.start_span("overflow", SpanKind::Internal, None);
+        tracer.start_span("unsampled", SpanKind::Internal, Some(&parent));
+
+        let spans = queue.take();
+
+        assert_eq!(2, spans.len());
+        assert_eq!("child", spans[0].name);
+        assert_eq!(Some("upstream failed"), spans[0].error.as_deref());
+        assert_eq!("root", spans[1].name);
+        assert_eq!(spans[1].context.trace_id, spans[0].context.trace_id);
+        assert_eq!(Some(spans[1].context.span_id), spans[0].parent_span_id);
+        assert!(queue.take().is_empty());
+    }
+}
+
+lazy_static! {
+    static ref TRACER: RwLock<Tracer> = RwLock::new(Tracer::disabled());
+}
+
+/// Replaces the tracer returned by `tracer`, which doesn't export spans by default.
+pub fn set_tracer(tracer: Tracer) {
+    *TRACER.write().unwrap() = tracer;
+}
+
+pub fn tracer() -> Tracer {
+    TRACER.read().unwrap().clone()
+}
+
+/// Position of a span in a trace, see https://www.w3.org/TR/trace-context/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parses the value of a `traceparent` header.
+    pub fn from_traceparent(value: &str) -> Option<TraceContext> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+
+        // later versions may append fields, but keep the ones of version 00
+        match version {
+            "00" if parts.next().is_none() => {}
+            "00" | "ff" => return None,
+            _ if version.len() == 2 && u8::from_str_radix(version, 16).is_ok() => {}
+            _ => return None,
+        }
+
+        let mut context = TraceContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+        };
+
+        parse_hex(trace_id, &mut context.trace_id)?;
+        parse_hex(span_id, &mut context.span_id)?;
+
+        if context.trace_id == [0; 16] || context.span_id == [0; 8] || flags.len() != 2 {
+            return None;
+        }
+
+        Some(context)
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            otlp::hex(&self.trace_id),
+            otlp::hex(&self.span_id),
+            u8::from(self.sampled)
+        )
+    }
+}
+
+fn parse_hex(value: &str, bytes: &mut [u8]) -> Option<()> {
+    if value.len() != bytes.len() * 2 {
+        return None;
+    }
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(value.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Internal,
+    Server,
+    Client,
+}
+
+/// Starts spans and exports the sampled ones once they end.
+#[derive(Clone)]
+pub struct Tracer {
+    queue: Option<Arc<SpanQueue>>,
+    sampling_ratio: f64,
+}
+
+impl Tracer {
+    /// New traces aren't sampled, the upstream would record traces whose parents never arrive.
+    pub fn disabled() -> Tracer {
+        Tracer {
+            queue: None,
+            sampling_ratio: 0.0,
+        }
+    }
+
+    /// Exports the spans to the OTLP collector of the config.
+    pub fn from_config(http_client: Client, config: &config::Tracing) -> Tracer {
+        let queue = Arc::new(SpanQueue::new(config.max_queued_spans));
+
+        start_export_spans_loop(
+            Arc::downgrade(&queue),
+            http_client,
+            config.endpoint.clone(),
+            config.service_name.clone(),
+            config.export_interval,
+        );
+
+        Tracer {
+            queue: Some(queue),
+            sampling_ratio: config.sampling_ratio,
+        }
+    }
+
+    /// Starts a span, which continues the trace of `parent` or a new one.
+    pub fn start_span(
+        &self,
+        name: &'static str,
+        kind: SpanKind,
+        parent: Option<&TraceContext>,
+    ) -> Span {
+        let (trace_id, sampled) = match parent {
+            Some(parent) => (parent.trace_id, parent.sampled),
+            None => {
+                let trace_id = rand::random();
+
+                (trace_id, is_sampled(&trace_id, self.sampling_ratio))
+            }
+        };
+        let context = TraceContext {
+            trace_id,
+            span_id: rand::random(),
+            sampled,
+        };
+
+        Span {
+            tracer: self.clone(),
+            data: SpanData {
+                name,
+                kind,
+                context,
+                parent_span_id: parent.map(|parent| parent.span_id),
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: vec![],
+                error: None,
+            },
+        }
+    }
+}
+
+/// Whether a new trace is sampled, decided on the trace id like the `TraceIdRatioBased`
+/// sampler of OpenTelemetry.
+fn is_sampled(trace_id: &[u8; 16], sampling_ratio: f64) -> bool {
+    if sampling_ratio >= 1.0 {
+        return true;
+    }
+
+    let mut random_part = [0; 8];
+    random_part.copy_from_slice(&trace_id[8..]);
+
+    ((u64::from_be_bytes(random_part) >> 1) as f64) < sampling_ratio * (1u64 << 63) as f64
+}
+
+/// Operation of a trace, ends when it is dropped.
+pub struct Span {
+    tracer: Tracer,
+    data: SpanData,
+}
+
+impl Span {
+    pub fn child(&self, name: &'static str, kind: SpanKind) -> Span {
+        self.tracer.start_span(name, kind, Some(&self.data.context))
+    }
+
+    pub fn context(&self) -> &TraceContext {
+        &self.data.context
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: impl ToString) {
+        self.data.attributes.push((key, value.to_string()));
+    }
+
+    /// Marks the span as failed.
+    pub fn set_error(&mut self, message: impl ToString) {
+        self.data.error = Some(message.to_string());
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Span")
+            .field("name", &self.data.name)
+            .field("context", &self.data.context)
+            .finish()
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let queue = match &self.tracer.queue {
+            Some(queue) if self.data.context.sampled => queue,
+            _ => return,
+        };
+
+        let mut data = SpanData {
+            attributes: mem::take(&mut self.data.attributes),
+            error: self.data.error.take(),
+            ..self.data
+        };
+        data.end = SystemTime::now();
+
+        queue.push(data);
+    }
+}
+
+/// Span that ended
+struct SpanData {
+    name: &'static str,
+    kind: SpanKind,
+    context: TraceContext,
+    parent_span_id: Option<[u8; 8]>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    error: Option<String>,
+}
+
+/// Ended spans waiting for their export
+struct SpanQueue {
+    spans: Mutex<Vec<SpanData>>,
+    max_spans: usize,
+}
+
+impl SpanQueue {
+    fn new(max_spans: usize) -> SpanQueue {
+        SpanQueue {
+            spans: Mutex::new(vec![]),
+            max_spans,
+        }
+    }
+
+    fn push(&self, span: SpanData) {
+        let mut spans = self.spans.lock().unwrap();
+
+        if spans.len() < self.max_spans {
+            spans.push(span);
+        }
+    }
+
+    fn take(&self) -> Vec<SpanData> {
+        mem::take(&mut *self.spans.lock().unwrap())
+    }
+}
+
+fn start_export_spans_loop(
+    queue: Weak<SpanQueue>,
+    http_client: Client,
+    endpoint: Url,
+    service_name: String,
+    export_interval: Duration,
+) {
+    info!("Start export spans loop");
+
+    tokio::spawn(async move {
+        export_spans_loop(queue, http_client, endpoint, service_name, export_interval).await
+    });
+}
+
+async fn export_spans_loop(
+    queue: Weak<SpanQueue>,
+    http_client: Client,
+    endpoint: Url,
+    service_name: String,
+    export_interval: Duration,
+) {
+    let mut interval = time::interval(export_interval);
+
+    loop {
+        interval.tick().await;
+
+        let spans = match queue.upgrade() {
+            Some(queue) => queue.take(),
+            None => {
+                info!("Couldn't get reference to the span queue, ending export loop");
+                break;
+            }
+        };
+
+        if spans.is_empty() {
+            continue;
+        }
+
+        if let Err(e) = otlp::export(&http_client, &endpoint, &service_name, &spans).await {
+            error!("Failed to export {} spans: {:?}", spans.len(), e);
+        }
+    }
+}
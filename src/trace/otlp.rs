@@ -0,0 +1,153 @@
+use super::{SpanData, SpanKind};
+use anyhow::Context;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceContext;
+    use std::time::Duration;
+
+    #[test]
+    fn test_encode_spans() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_600_000_000_123);
+        let span = SpanData {
+            name: "fetch_playlist_from_upstream",
+            kind: SpanKind::Client,
+            context: TraceContext {
+                trace_id: [0xab; 16],
+                span_id: [0x01; 8],
+                sampled: true,
+            },
+            parent_span_id: Some([0x02; 8]),
+            start,
+            end: start + Duration::from_millis(50),
+            attributes: vec![("http.status_code", String::from("502"))],
+            error: Some(String::from("Bad gateway")),
+        };
+
+        let request = encode("grosp", &[span]);
+
+        assert_eq!(
+            json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": [
+                            {"key": "service.name", "value": {"stringValue": "grosp"}}
+                        ]
+                    },
+                    "scopeSpans": [{
+                        "scope": {"name": "balancing-playlist-spreader"},
+                        "spans": [{
+                            "traceId": "abababababababababababababababab",
+                            "spanId": "0101010101010101",
+                            "parentSpanId": "0202020202020202",
+                            "name": "fetch_playlist_from_upstream",
+                            "kind": 3,
+                            "startTimeUnixNano": "1600000000123000000",
+                            "endTimeUnixNano": "1600000000173000000",
+                            "attributes": [
+                                {"key": "http.status_code", "value": {"stringValue": "502"}}
+                            ],
+                            "status": {"code": 2, "message": "Bad gateway"}
+                        }]
+                    }]
+                }]
+            }),
+            request
+        );
+    }
+}
+
+/// Sends the spans to the OTLP/HTTP endpoint of a collector in the JSON encoding,
+/// see https://opentelemetry.io/docs/specs/otlp/#otlphttp
+pub(super) async fn export(
+    http_client: &Client,
+    endpoint: &Url,
+    service_name: &str,
+    spans: &[SpanData],
+) -> anyhow::Result<()> {
+    let body = serde_json::to_vec(&encode(service_name, spans))?;
+
+    http_client
+        .post(endpoint.clone())
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to send spans to `{}`", endpoint))?;
+
+    Ok(())
+}
+
+fn encode(service_name: &str, spans: &[SpanData]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", service_name)]
+            },
+            "scopeSpans": [{
+                "scope": {"name": "balancing-playlist-spreader"},
+                "spans": spans.iter().map(encode_span).collect::<Vec<_>>()
+            }]
+        }]
+    })
+}
+
+fn encode_span(span: &SpanData) -> Value {
+    let mut encoded = json!({
+        "traceId": hex(&span.context.trace_id),
+        "spanId": hex(&span.context.span_id),
+        "name": span.name,
+        "kind": match span.kind {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+            SpanKind::Client => 3,
+        },
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": span
+            .attributes
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect::<Vec<_>>(),
+    });
+
+    if let Some(parent_span_id) = &span.parent_span_id {
+        encoded["parentSpanId"] = json!(hex(parent_span_id));
+    }
+
+    if let Some(error) = &span.error {
+        encoded["status"] = json!({"code": 2, "message": error});
+    }
+
+    encoded
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// 64 bit integers are encoded as strings, they don't fit into the numbers of JavaScript.
+fn unix_nanos(time: SystemTime) -> String {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_nanos())
+        .unwrap_or(0);
+
+    nanos.to_string()
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}